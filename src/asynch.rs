@@ -0,0 +1,724 @@
+//! An asynchronous version of the driver built on `embedded-hal-async`.
+//!
+//! The [`Mcp23s17`] in this module mirrors the blocking [`crate::Mcp23s17`] but talks to
+//! the device over any `embedded_hal_async::spi::SpiDevice`. In addition, the
+//! device's `INTA` output must be connected to a host GPIO that implements
+//! `embedded_hal_async::digital::Wait` so that an [`InputPin`] can await changes on its
+//! input through its own implementation of `Wait`.
+//!
+//! The registers are accessed with the same bookkeeping as the blocking driver: they
+//! follow the [`BankMode`], and the shadow register cache and the process lock can be
+//! enabled.
+//!
+//! ```no_run
+//! # async fn example<SPI, INT>(spi: SPI, int: INT) -> rppal_mcp23s17::Result<()>
+//! # where
+//! #     SPI: embedded_hal_async::spi::SpiDevice,
+//! #     INT: embedded_hal_async::digital::Wait,
+//! # {
+//! use embedded_hal_async::digital::Wait;
+//! use rppal_mcp23s17::{HardwareAddress, Port, asynch::Mcp23s17};
+//!
+//! let mcp23s17 = Mcp23s17::new(HardwareAddress::new(0)?, spi, int).await?;
+//! let mut button = mcp23s17.get(Port::GpioA, 0)?.into_pullup_input_pin().await?;
+//!
+//! // Wait for the button to be pressed (pulled low).
+//! button.wait_for_falling_edge().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Interrupts
+//!
+//! The `Wait` implementation is driven by the same interrupt configuration as
+//! [`InputPin::set_interrupt_mode()`]: whilst waiting, the pin's interrupt mode is
+//! set to suit the condition being awaited and restored afterwards. Restoring it needs
+//! a transfer so, if the wait is cancelled by dropping its future, the pin is left
+//! interrupting on the awaited condition until the next wait or
+//! [`InputPin::set_interrupt_mode()`] restores or replaces it. The `INT` line is
+//! awaited at the active level given by [`IOCON::ODR`] and [`IOCON::INTPOL`], which
+//! are left as they were configured. For the pins of port B to be awaited, either
+//! [`IOCON::MIRROR`] must be set so that `INTA` also reports port B, or `INTB` must
+//! drive the same host GPIO (_e.g._ with both open-drain).
+//!
+//! Several pins may be awaited concurrently: whichever waiter sees the `INT` line
+//! asserted services the interrupt flag and capture registers on behalf of all of them.
+
+use std::sync::{Arc, Mutex as SyncMutex, MutexGuard, PoisonError};
+
+use async_lock::Mutex;
+use embedded_hal_async::{digital::Wait, spi::SpiDevice};
+use log::{debug, error};
+
+use super::{
+    BankMode, HardwareAddress, IOCON, InterruptMode, Level, Mcp23s17Error, Port, RegisterAddress,
+    RegisterMap, Result, SpiCommand, pin::InputPinMode,
+};
+
+/// The registers of the MCP23S17 accessed over the SPI device, along with interrupts
+/// that have been serviced but not yet consumed by the waiting pin.
+#[derive(Debug)]
+struct Registers<SPI> {
+    spi: SPI,
+    address: HardwareAddress,
+    /// What is known about the device's registers.
+    register_map: RegisterMap,
+    /// `INTF` bits for each port that are yet to be consumed by a waiting pin.
+    pending_flags: [u8; 2],
+    /// The most recent `INTCAP` values for each port.
+    captures: [u8; 2],
+}
+
+/// State shared between the [`Mcp23s17`] and the pins taken from it.
+#[derive(Debug)]
+struct Shared<SPI, INT> {
+    registers: Mutex<Registers<SPI>>,
+    /// The host GPIO connected to the MCP23S17 `INTA` output.
+    interrupt_line: Mutex<INT>,
+    /// Keep track of which pins are in use on `GPIOA` and `GPIOB`.
+    pins_taken: SyncMutex<[[bool; 8]; 2]>,
+}
+
+impl<SPI, INT> Shared<SPI, INT> {
+    /// Get exclusive access to the record of the pins in use.
+    ///
+    /// A panic whilst the record was locked doesn't leave it inconsistent, so the
+    /// poisoning is ignored.
+    fn pins_taken(&self) -> MutexGuard<'_, [[bool; 8]; 2]> {
+        self.pins_taken
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An asynchronous MCP23S17 I/O expander.
+///
+/// See the [module documentation][self] for details.
+#[derive(Debug)]
+pub struct Mcp23s17<SPI, INT> {
+    shared: Arc<Shared<SPI, INT>>,
+}
+
+/// An unconfigured GPIO pin of an asynchronous [`Mcp23s17`].
+#[derive(Debug)]
+pub struct Pin<SPI, INT> {
+    port: Port,
+    pin: u8,
+    shared: Arc<Shared<SPI, INT>>,
+}
+
+/// A pin on a GPIO port of an asynchronous [`Mcp23s17`] configured for input.
+///
+/// Note that, since `Drop` cannot be asynchronous, dropping an `InputPin` with
+/// interrupts enabled leaves them enabled on the device. Use
+/// [`InputPin::set_interrupt_mode()`] with [`InterruptMode::None`] first if that
+/// matters.
+#[derive(Debug)]
+pub struct InputPin<SPI, INT> {
+    pin: Pin<SPI, INT>,
+    interrupt_mode: InterruptMode,
+
+    /// The interrupt mode to restore once a wait that was cancelled part way through
+    /// has left the awaited mode set.
+    restore_mode: Option<InterruptMode>,
+}
+
+/// A pin on a GPIO port of an asynchronous [`Mcp23s17`] configured for output.
+#[derive(Debug)]
+pub struct OutputPin<SPI, INT> {
+    pin: Pin<SPI, INT>,
+}
+
+/// Index of the `port` into the per-port arrays.
+fn port_index(port: Port) -> usize {
+    match port {
+        Port::GpioA => 0,
+        Port::GpioB => 1,
+    }
+}
+
+/// Select the port A or port B flavour of a register.
+fn port_register(port: Port, a: RegisterAddress, b: RegisterAddress) -> RegisterAddress {
+    match port {
+        Port::GpioA => a,
+        Port::GpioB => b,
+    }
+}
+
+impl<SPI: SpiDevice> Registers<SPI> {
+    /// Perform a single register transfer at the physical `register_address`,
+    /// returning the byte read back.
+    async fn transfer(
+        &mut self,
+        command: SpiCommand,
+        register_address: u8,
+        data: u8,
+    ) -> Result<u8> {
+        let mut read_buffer = [0u8; 3];
+        let write_buffer = [self.address.control_byte(command), register_address, data];
+
+        self.spi
+            .transfer(&mut read_buffer, &write_buffer)
+            .await
+            .map_err(|e| {
+                use embedded_hal::spi::Error;
+                error!("SPI device error ({:?})", e.kind());
+                Mcp23s17Error::SpiDeviceError(e.kind())
+            })?;
+        Ok(read_buffer[2])
+    }
+
+    /// Read an MCP23S17 register, from the shadow register cache if possible.
+    async fn read(&mut self, register: RegisterAddress) -> Result<u8> {
+        match self.register_map.cached(register)? {
+            Some(value) => Ok(value),
+            None => self.read_device(register).await,
+        }
+    }
+
+    /// Read an MCP23S17 register from the device.
+    async fn read_device(&mut self, register: RegisterAddress) -> Result<u8> {
+        debug!("Read {register:?}");
+        let address = self.register_map.address(register)?;
+        let data = self.transfer(SpiCommand::Read, address, 0).await?;
+        debug!("Read value = 0x{data:02x}");
+        self.register_map.track_read(register, data);
+        Ok(data)
+    }
+
+    /// Write an MCP23S17 register.
+    async fn write(&mut self, register: RegisterAddress, data: u8) -> Result<()> {
+        let data = self.register_map.value_to_write(register, data);
+        debug!("Write 0x{data:02x} to {register:?}");
+        let address = self.register_map.address(register)?;
+        self.transfer(SpiCommand::Write, address, data).await?;
+        self.register_map.track_write(register, data);
+        Ok(())
+    }
+
+    /// Write `IOCON` and verify that it reads back from the device, at its new address
+    /// if the bank mode changed.
+    async fn write_iocon(&mut self, written: u8) -> Result<()> {
+        let written = self
+            .register_map
+            .value_to_write(RegisterAddress::IOCON, written);
+        self.write(RegisterAddress::IOCON, written).await?;
+        let read = self.read_device(RegisterAddress::IOCON).await?;
+        if read != written {
+            error!("IOCON read back as 0x{read:02x} after writing 0x{written:02x}");
+            return Err(Mcp23s17Error::ConfigurationNotApplied { written, read });
+        }
+        Ok(())
+    }
+
+    /// Reload the shadow register cache (if enabled) from the device.
+    async fn resync(&mut self) -> Result<()> {
+        let registers = self.register_map.registers_to_cache();
+        if registers.is_empty() {
+            return Ok(());
+        }
+        debug!("Resync register cache");
+        let mut cache = [0u8; RegisterAddress::LENGTH];
+        for register in registers {
+            cache[register as usize] = self.read_device(register).await?;
+        }
+        self.register_map.cache = Some(cache);
+        Ok(())
+    }
+
+    /// Write the register with the result of `modify` on its current value, holding
+    /// the process lock (if enabled) throughout.
+    async fn read_modify_write(
+        &mut self,
+        register: RegisterAddress,
+        modify: impl FnOnce(u8) -> u8,
+    ) -> Result<()> {
+        #[cfg(feature = "process-lock")]
+        let process_lock = self.register_map.process_lock();
+        #[cfg(feature = "process-lock")]
+        let _guard = process_lock
+            .as_deref()
+            .map(crate::ProcessLock::lock)
+            .transpose()?;
+        let value = match self.register_map.cached_for_modify(register)? {
+            Some(value) => value,
+            None => self.read_device(register).await?,
+        };
+        self.write(register, modify(value)).await
+    }
+
+    /// Set the specified bit (0-7) in the register.
+    async fn set_bit(&mut self, register: RegisterAddress, bit: u8) -> Result<()> {
+        debug!("Set bit {bit} in {register:?}");
+        self.read_modify_write(register, |value| value | (0x01 << bit))
+            .await
+    }
+
+    /// Clear the specified bit (0-7) in the register.
+    async fn clear_bit(&mut self, register: RegisterAddress, bit: u8) -> Result<()> {
+        debug!("Clear bit {bit} in {register:?}");
+        self.read_modify_write(register, |value| value & !(0x01 << bit))
+            .await
+    }
+
+    /// Read the `INTF` and then `INTCAP` registers for both ports (which also clears the
+    /// interrupt) and record the interrupts for the waiting pins.
+    ///
+    /// Each register is read in its own transfer so that the reads don't depend on the
+    /// bank mode or on sequential operation.
+    async fn service_interrupts(&mut self) -> Result<()> {
+        let intf = [
+            self.read_device(RegisterAddress::INTFA).await?,
+            self.read_device(RegisterAddress::INTFB).await?,
+        ];
+        let intcap = [
+            self.read_device(RegisterAddress::INTCAPA).await?,
+            self.read_device(RegisterAddress::INTCAPB).await?,
+        ];
+        debug!("Serviced interrupts INTF={intf:?} INTCAP={intcap:?}");
+        for (pending_flags, flags) in self.pending_flags.iter_mut().zip(intf) {
+            *pending_flags |= flags;
+        }
+        self.captures = intcap;
+        Ok(())
+    }
+
+    /// Whether the `INT` output is active-low, as when it is open-drain.
+    fn interrupt_active_low(&self) -> bool {
+        let iocon = self.register_map.iocon;
+        iocon.contains(IOCON::ODR) || !iocon.contains(IOCON::INTPOL)
+    }
+}
+
+impl<SPI: SpiDevice, INT: Wait> Mcp23s17<SPI, INT> {
+    /// Create an asynchronous MCP23S17 accessed over `spi` with its `INTA` output
+    /// connected to `interrupt_line`.
+    ///
+    /// Nothing is written to the device: `IOCON` is read from its address in
+    /// [`BankMode::Interleaved`] (the power-on default) to learn how the `INT` output
+    /// is configured. If the device is in [`BankMode::Segregated`] then use
+    /// [`Mcp23s17::assume_bank_mode()`] to tell the driver.
+    pub async fn new(address: HardwareAddress, spi: SPI, interrupt_line: INT) -> Result<Self> {
+        let mut registers = Registers {
+            spi,
+            address,
+            register_map: RegisterMap::default(),
+            pending_flags: [0; 2],
+            captures: [0; 2],
+        };
+        registers.read_device(RegisterAddress::IOCON).await?;
+
+        Ok(Mcp23s17 {
+            shared: Arc::new(Shared {
+                registers: Mutex::new(registers),
+                interrupt_line: Mutex::new(interrupt_line),
+                pins_taken: SyncMutex::new([[false; 8]; 2]),
+            }),
+        })
+    }
+
+    /// Read a byte from the MCP23S17 register at the address `register`.
+    pub async fn read(&self, register: RegisterAddress) -> Result<u8> {
+        self.shared.registers.lock().await.read(register).await
+    }
+
+    /// Write the byte `data` to the MCP23S17 register at address `register`.
+    pub async fn write(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        self.shared
+            .registers
+            .lock()
+            .await
+            .write(register, data)
+            .await
+    }
+
+    /// The register arrangement that the driver believes the device is using.
+    pub async fn bank_mode(&self) -> BankMode {
+        self.shared.registers.lock().await.register_map.bank_mode()
+    }
+
+    /// Switch the device to the `bank_mode`.
+    ///
+    /// See [`crate::Mcp23s17::set_bank_mode()`].
+    pub async fn set_bank_mode(&self, bank_mode: BankMode) -> Result<()> {
+        let mut registers = self.shared.registers.lock().await;
+        registers.register_map.check_bankable()?;
+        debug!("Set bank mode {bank_mode}");
+        let mut iocon =
+            IOCON::from_bits_retain(registers.read_device(RegisterAddress::IOCON).await?);
+        iocon.set(IOCON::BANK, bank_mode == BankMode::Segregated);
+        registers.write_iocon(iocon.bits()).await
+    }
+
+    /// Tell the driver that the device is already in the `bank_mode` (_e.g._ because
+    /// it was configured by other software) without writing `IOCON`.
+    ///
+    /// `IOCON` is read again from its address in that mode and, if the register cache
+    /// is enabled, the cache is reloaded from the device.
+    pub async fn assume_bank_mode(&self, bank_mode: BankMode) -> Result<()> {
+        let mut registers = self.shared.registers.lock().await;
+        registers.register_map.assume_bank_mode(bank_mode)?;
+        registers.read_device(RegisterAddress::IOCON).await?;
+        registers.resync().await
+    }
+
+    /// Enable the shadow register cache, loading it from the device.
+    ///
+    /// See [`crate::Mcp23s17::enable_cache()`].
+    pub async fn enable_cache(&self) -> Result<()> {
+        let mut registers = self.shared.registers.lock().await;
+        registers.register_map.cache = Some([0u8; RegisterAddress::LENGTH]);
+        registers.resync().await
+    }
+
+    /// Disable the shadow register cache so that every access goes to the device.
+    pub async fn disable_cache(&self) {
+        self.shared.registers.lock().await.register_map.cache = None;
+    }
+
+    /// Reload the shadow register cache from the device.
+    ///
+    /// Does nothing if the cache is not enabled.
+    pub async fn resync(&self) -> Result<()> {
+        self.shared.registers.lock().await.resync().await
+    }
+
+    /// Take an advisory lock on the lock file at `path` around each read-modify-write
+    /// of the device's registers.
+    ///
+    /// See [`crate::Mcp23s17::enable_process_lock()`]. Taking the lock blocks the
+    /// executor's thread whilst another process holds it.
+    #[cfg(feature = "process-lock")]
+    pub async fn enable_process_lock(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let process_lock = crate::ProcessLock::open(path)?;
+        self.shared.registers.lock().await.register_map.process_lock = Some(Arc::new(process_lock));
+        Ok(())
+    }
+
+    /// Stop taking the advisory lock, closing the lock file.
+    #[cfg(feature = "process-lock")]
+    pub async fn disable_process_lock(&self) {
+        self.shared.registers.lock().await.register_map.process_lock = None;
+    }
+
+    /// Returns a [`Pin`] for the specified GPIO port and pin number.
+    ///
+    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
+    /// `get()` returns `Err(`[`Mcp23s17Error::PinNotAvailable`]`)`.
+    pub fn get(&self, port: Port, pin: u8) -> Result<Pin<SPI, INT>> {
+        if pin > 7 {
+            return Err(Mcp23s17Error::PinNotAvailable(pin));
+        }
+        let mut pins_taken = self.shared.pins_taken();
+        if pins_taken[port_index(port)][pin as usize] {
+            return Err(Mcp23s17Error::PinNotAvailable(pin));
+        }
+        pins_taken[port_index(port)][pin as usize] = true;
+        Ok(Pin {
+            port,
+            pin,
+            shared: self.shared.clone(),
+        })
+    }
+}
+
+impl<SPI: SpiDevice, INT: Wait> Pin<SPI, INT> {
+    /// Read the state of the pin.
+    pub async fn read(&self) -> Result<Level> {
+        let gpio = port_register(self.port, RegisterAddress::GPIOA, RegisterAddress::GPIOB);
+        let value = self.shared.registers.lock().await.read(gpio).await?;
+        Ok(Level::from(value & (0x01 << self.pin)))
+    }
+
+    /// Turn the unconfigured `Pin` into a high-impedance `InputPin`.
+    pub async fn into_input_pin(self) -> Result<InputPin<SPI, INT>> {
+        InputPin::new(self, InputPinMode::HighImpedance).await
+    }
+
+    /// Turn the unconfigured `Pin` into an `InputPin` with the internal pull-up
+    /// resistor connected.
+    pub async fn into_pullup_input_pin(self) -> Result<InputPin<SPI, INT>> {
+        InputPin::new(self, InputPinMode::PullUp).await
+    }
+
+    /// Turn the unconfigured `Pin` into an `OutputPin`.
+    pub async fn into_output_pin(self) -> Result<OutputPin<SPI, INT>> {
+        OutputPin::new(self).await
+    }
+}
+
+impl<SPI, INT> Drop for Pin<SPI, INT> {
+    fn drop(&mut self) {
+        self.shared.pins_taken()[port_index(self.port)][self.pin as usize] = false;
+    }
+}
+
+impl<SPI: SpiDevice, INT: Wait> InputPin<SPI, INT> {
+    /// Constructs an `InputPin` consuming the unconfigured `Pin` in the process.
+    async fn new(pin: Pin<SPI, INT>, mode: InputPinMode) -> Result<Self> {
+        {
+            let mut registers = pin.shared.registers.lock().await;
+            registers
+                .set_bit(
+                    port_register(pin.port, RegisterAddress::IODIRA, RegisterAddress::IODIRB),
+                    pin.pin,
+                )
+                .await?;
+            let gppu = port_register(pin.port, RegisterAddress::GPPUA, RegisterAddress::GPPUB);
+            match mode {
+                InputPinMode::HighImpedance => registers.clear_bit(gppu, pin.pin).await?,
+                InputPinMode::PullUp => registers.set_bit(gppu, pin.pin).await?,
+            }
+        }
+        Ok(InputPin {
+            pin,
+            interrupt_mode: InterruptMode::None,
+            restore_mode: None,
+        })
+    }
+
+    /// Reads the pin's logic level.
+    pub async fn read(&self) -> Result<Level> {
+        self.pin.read().await
+    }
+
+    /// Gets the pin's bit number (0-7).
+    pub fn get_pin_number(&self) -> u8 {
+        self.pin.pin
+    }
+
+    /// Set the [`InputPin`] to the requested `mode` (_i.e._ which edge(s) on the input
+    /// trigger an interrupt.)
+    ///
+    /// The registers are set as described for the blocking
+    /// [`InputPin::set_interrupt_mode()`][crate::InputPin::set_interrupt_mode]. This
+    /// replaces any interrupt mode left set by a cancelled wait.
+    pub async fn set_interrupt_mode(&mut self, mode: InterruptMode) -> Result<()> {
+        self.restore_mode = None;
+        self.apply_interrupt_mode(mode).await
+    }
+
+    /// Set the pin's interrupt registers for `mode`.
+    async fn apply_interrupt_mode(&mut self, mode: InterruptMode) -> Result<()> {
+        let port = self.pin.port;
+        let bit = self.pin.pin;
+        let gpinten = port_register(port, RegisterAddress::GPINTENA, RegisterAddress::GPINTENB);
+        let intcon = port_register(port, RegisterAddress::INTCONA, RegisterAddress::INTCONB);
+        let defval = port_register(port, RegisterAddress::DEFVALA, RegisterAddress::DEFVALB);
+
+        // As for the blocking pin, GPINTEN is set last so that the correct criteria are
+        // in place before interrupts are enabled.
+        let mut registers = self.pin.shared.registers.lock().await;
+        match mode {
+            InterruptMode::None => {
+                registers.clear_bit(gpinten, bit).await?;
+            }
+            InterruptMode::ActiveHigh => {
+                registers.set_bit(intcon, bit).await?;
+                registers.clear_bit(defval, bit).await?;
+                registers.set_bit(gpinten, bit).await?;
+            }
+            InterruptMode::ActiveLow => {
+                registers.set_bit(intcon, bit).await?;
+                registers.set_bit(defval, bit).await?;
+                registers.set_bit(gpinten, bit).await?;
+            }
+            InterruptMode::BothEdges => {
+                registers.clear_bit(intcon, bit).await?;
+                registers.set_bit(gpinten, bit).await?;
+            }
+        }
+        self.interrupt_mode = mode;
+        Ok(())
+    }
+
+    /// Consume any serviced interrupt for this pin, returning the captured level.
+    async fn take_pending(&self) -> Option<Level> {
+        let port = port_index(self.pin.port);
+        let mask = 0x01 << self.pin.pin;
+        let mut registers = self.pin.shared.registers.lock().await;
+        if registers.pending_flags[port] & mask == 0 {
+            return None;
+        }
+        registers.pending_flags[port] &= !mask;
+        Some(Level::from(registers.captures[port] & mask))
+    }
+
+    /// Wait for the next interrupt raised by this pin, returning the captured level.
+    async fn next_interrupt(&self) -> Result<Level> {
+        loop {
+            if let Some(level) = self.take_pending().await {
+                return Ok(level);
+            }
+
+            // Only the holder of the interrupt line services the interrupt registers so
+            // check again once it is ours in case another waiter serviced our interrupt
+            // whilst we were queued.
+            let mut interrupt_line = self.pin.shared.interrupt_line.lock().await;
+            if let Some(level) = self.take_pending().await {
+                return Ok(level);
+            }
+            let active_low = self
+                .pin
+                .shared
+                .registers
+                .lock()
+                .await
+                .interrupt_active_low();
+            let asserted = if active_low {
+                interrupt_line.wait_for_low().await
+            } else {
+                interrupt_line.wait_for_high().await
+            };
+            asserted.map_err(|e| {
+                use embedded_hal::digital::Error;
+                Mcp23s17Error::InterruptLineError(e.kind())
+            })?;
+            self.pin
+                .shared
+                .registers
+                .lock()
+                .await
+                .service_interrupts()
+                .await?;
+        }
+    }
+
+    /// Restore the interrupt mode left behind by a wait that was cancelled.
+    async fn restore_interrupt_mode(&mut self) -> Result<()> {
+        if let Some(mode) = self.restore_mode {
+            debug!("Restore interrupt mode {mode:?} after a cancelled wait");
+            self.apply_interrupt_mode(mode).await?;
+            self.restore_mode = None;
+        }
+        Ok(())
+    }
+
+    /// Wait, with the pin's interrupt set to `mode`, for an interrupt whose captured
+    /// level satisfies `accept`, restoring the original interrupt mode afterwards.
+    ///
+    /// If waiting fails then that error is returned, even if restoring the interrupt
+    /// mode fails too. If the future is dropped before it completes, the original mode
+    /// is restored at the start of the next wait instead.
+    async fn wait_for_interrupt(
+        &mut self,
+        mode: InterruptMode,
+        accept: fn(Level) -> bool,
+    ) -> Result<()> {
+        self.restore_interrupt_mode().await?;
+        let original_mode = self.interrupt_mode;
+        self.restore_mode = Some(original_mode);
+        self.apply_interrupt_mode(mode).await?;
+
+        // Discard any stale interrupt so that only fresh ones are considered.
+        let _ = self.take_pending().await;
+        let result = loop {
+            match self.next_interrupt().await {
+                Ok(level) if accept(level) => break Ok(()),
+                Ok(_) => continue,
+                Err(e) => break Err(e),
+            }
+        };
+
+        let restored = self.restore_interrupt_mode().await;
+        if let (Err(_), Err(e)) = (&result, &restored) {
+            error!("Failed to restore the interrupt mode after a failed wait: {e}");
+        }
+        result.and(restored)
+    }
+}
+
+impl<SPI, INT> embedded_hal::digital::ErrorType for InputPin<SPI, INT> {
+    type Error = Mcp23s17Error;
+}
+
+impl<SPI: SpiDevice, INT: Wait> Wait for InputPin<SPI, INT> {
+    async fn wait_for_high(&mut self) -> Result<()> {
+        self.restore_interrupt_mode().await?;
+        if self.read().await? == Level::High {
+            return Ok(());
+        }
+        self.wait_for_interrupt(InterruptMode::ActiveHigh, |_| true)
+            .await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<()> {
+        self.restore_interrupt_mode().await?;
+        if self.read().await? == Level::Low {
+            return Ok(());
+        }
+        self.wait_for_interrupt(InterruptMode::ActiveLow, |_| true)
+            .await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<()> {
+        self.wait_for_interrupt(InterruptMode::BothEdges, |level| level == Level::High)
+            .await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<()> {
+        self.wait_for_interrupt(InterruptMode::BothEdges, |level| level == Level::Low)
+            .await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<()> {
+        self.wait_for_interrupt(InterruptMode::BothEdges, |_| true)
+            .await
+    }
+}
+
+impl<SPI: SpiDevice, INT: Wait> OutputPin<SPI, INT> {
+    /// Constructs an `OutputPin` consuming the unconfigured `Pin` in the process.
+    async fn new(pin: Pin<SPI, INT>) -> Result<Self> {
+        {
+            let mut registers = pin.shared.registers.lock().await;
+            registers
+                .clear_bit(
+                    port_register(pin.port, RegisterAddress::IODIRA, RegisterAddress::IODIRB),
+                    pin.pin,
+                )
+                .await?;
+            registers
+                .clear_bit(
+                    port_register(pin.port, RegisterAddress::GPPUA, RegisterAddress::GPPUB),
+                    pin.pin,
+                )
+                .await?;
+        }
+        Ok(OutputPin { pin })
+    }
+
+    /// Set the state of the pin.
+    pub async fn write(&self, level: Level) -> Result<()> {
+        let gpio = port_register(
+            self.pin.port,
+            RegisterAddress::GPIOA,
+            RegisterAddress::GPIOB,
+        );
+        let mut registers = self.pin.shared.registers.lock().await;
+        match level {
+            Level::Low => registers.clear_bit(gpio, self.pin.pin).await,
+            Level::High => registers.set_bit(gpio, self.pin.pin).await,
+        }
+    }
+
+    /// Set the output to `Level::High`.
+    pub async fn set_high(&self) -> Result<()> {
+        self.write(Level::High).await
+    }
+
+    /// Set the output to `Level::Low`.
+    pub async fn set_low(&self) -> Result<()> {
+        self.write(Level::Low).await
+    }
+
+    /// Reads the pin's logic level.
+    pub async fn read(&self) -> Result<Level> {
+        self.pin.read().await
+    }
+
+    /// Gets the pin's bit number (0-7).
+    pub fn get_pin_number(&self) -> u8 {
+        self.pin.pin
+    }
+}
@@ -0,0 +1,271 @@
+//! Several MCP23S17s sharing a single SPI chip select.
+//!
+//! With the hardware address pins enabled ([`IOCON::HAEN`]) up to eight MCP23S17s can
+//! share one chip select, each answering only to the messages whose control byte
+//! carries its [`HardwareAddress`]. An [`Mcp23s17Bus`] owns the one SPI handle for the
+//! bus and chip select and hands out an [`Mcp23s17`] for each address, refusing an
+//! address that is already in use. [`Mcp23s17Bus::discover()`] finds the addresses at
+//! which devices are fitted.
+//!
+//! ```no_run
+//! use rppal_mcp23s17::{ChipSelect, HardwareAddress, Mcp23s17Bus, Port, SpiBus, SpiMode};
+//!
+//! let bus = Mcp23s17Bus::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0)
+//!     .expect("Failed to create bus");
+//! let first = bus
+//!     .get(HardwareAddress::new(0).expect("Invalid hardware address"))
+//!     .expect("Failed to get MCP23S17");
+//! let second = bus
+//!     .get(HardwareAddress::new(1).expect("Invalid hardware address"))
+//!     .expect("Failed to get MCP23S17");
+//! let pin = second.get(Port::GpioB, 2).expect("Failed to get Pin");
+//! ```
+
+use log::debug;
+use rppal::spi::Spi;
+
+#[cfg(any(test, feature = "mockspi"))]
+use super::mock_spi::MockSpiBus;
+use super::{
+    ChipSelect, HardwareAddress, IOCON, Mcp23s17, Mcp23s17Error, Mcp23s17State, RegisterAddress,
+    RegisterLayout, Result, Shared, SpiBus, SpiCommand, SpiMode, Transport,
+};
+
+/// The record of which hardware addresses on a bus are in use.
+type AddressesTaken = Shared<[bool; HardwareAddress::MAX_HARDWARE_ADDRESS as usize + 1]>;
+
+/// The MCP23S17s sharing one SPI bus and chip select.
+#[derive(Debug)]
+pub struct Mcp23s17Bus<T: Transport = Spi> {
+    /// The transport shared by all the devices.
+    transport: Shared<T>,
+
+    /// The SPI bus, if known.
+    spi_bus: Option<SpiBus>,
+
+    /// Keep track of which hardware addresses are in use.
+    addresses_taken: AddressesTaken,
+}
+
+impl Mcp23s17Bus<Spi> {
+    /// Open the Raspberry Pi's SPI `spi_bus` with the `chip_select` and enable the
+    /// hardware address pins of all the devices on it.
+    ///
+    /// See [`Mcp23s17Bus::with_transport()`].
+    pub fn new(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Self> {
+        let spi = Spi::new(spi_bus, chip_select.into(), spi_clock, spi_mode)?;
+        Mcp23s17Bus::from_transport(spi, Some(spi_bus))
+    }
+}
+
+#[cfg(any(test, feature = "mockspi"))]
+impl Mcp23s17Bus<MockSpiBus> {
+    /// Create a bus with mock devices at each of the `addresses`.
+    pub fn new_mock(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+        addresses: &[HardwareAddress],
+    ) -> Result<Self> {
+        let spi = MockSpiBus::new(spi_bus, chip_select, spi_clock, spi_mode, addresses);
+        Mcp23s17Bus::from_transport(spi, Some(spi_bus))
+    }
+
+    /// In testing environments provide an API to read the registers of the mock device
+    /// at `address`.
+    pub fn get_mock_data(
+        &self,
+        address: HardwareAddress,
+        register: RegisterAddress,
+    ) -> (u8, usize, usize) {
+        self.transport
+            .lock()
+            .device(address)
+            .get_mock_data(register)
+    }
+
+    /// In testing environments provide an API to write the registers of the mock
+    /// device at `address`.
+    pub fn set_mock_data(&self, address: HardwareAddress, register: RegisterAddress, data: u8) {
+        self.transport
+            .lock()
+            .device(address)
+            .set_mock_data(register, data);
+    }
+}
+
+impl<T: Transport> Mcp23s17Bus<T> {
+    /// Create a bus over a user-supplied [`Transport`] and enable the hardware address
+    /// pins of all the devices on it.
+    ///
+    /// A device only looks at the address in the control byte once its hardware
+    /// address pins are enabled and, until then, answers to every address. So
+    /// [`IOCON::HAEN`] is written at every hardware address, which reaches all the
+    /// devices whatever their state. Since the devices answer together until then, no
+    /// bits read from them are written back: `IOCON` is left with only `HAEN` set (and
+    /// `BANK`, for a device in the banked layout), so configure each device once it
+    /// has been got from the bus. The devices may be in either
+    /// [`BankMode`][crate::BankMode], though a device left in `BankMode::Segregated`
+    /// must be told so with [`Mcp23s17::assume_bank_mode()`].
+    ///
+    /// The devices handed out by [`Mcp23s17Bus::get()`] keep `HAEN` set whenever they
+    /// write `IOCON`, including when they are configured or reset.
+    pub fn with_transport(transport: T) -> Result<Self> {
+        Mcp23s17Bus::from_transport(transport, None)
+    }
+
+    /// Take ownership of the transport and enable hardware addressing.
+    fn from_transport(transport: T, spi_bus: Option<SpiBus>) -> Result<Self> {
+        let bus = Mcp23s17Bus {
+            transport: Shared::new(transport),
+            spi_bus,
+            addresses_taken: Shared::default(),
+        };
+        bus.enable_hardware_addressing()?;
+        Ok(bus)
+    }
+
+    /// Write `IOCON` with only `HAEN` set at every hardware address, with `BANK` too
+    /// at the banked address.
+    ///
+    /// `IOCON` is at 0x0B in [`BankMode::Interleaved`][crate::BankMode::Interleaved] but
+    /// at 0x05 in [`BankMode::Segregated`][crate::BankMode::Segregated], where 0x0B is
+    /// unimplemented so ignores writes and reads as zero. So `HAEN` is first set at
+    /// 0x0B and, only if it doesn't read back, at 0x05 provided that reads with `BANK`
+    /// set. The output latch at 0x0A in the banked layout is never written, and nothing
+    /// read from the devices is written back since it may have come from several of
+    /// them at once.
+    fn enable_hardware_addressing(&self) -> Result<()> {
+        debug!("Enable hardware addressing on all devices");
+        let interleaved = RegisterLayout::Interleaved.address(RegisterAddress::IOCON2)?;
+        let banked = RegisterLayout::Banked.address(RegisterAddress::IOCON)?;
+        for address in 0..=HardwareAddress::MAX_HARDWARE_ADDRESS {
+            let address = HardwareAddress(address);
+            self.transfer(address, interleaved, Some(IOCON::HAEN.bits()))?;
+            let iocon = IOCON::from_bits_retain(self.transfer(address, interleaved, None)?);
+            if iocon.contains(IOCON::HAEN) {
+                continue;
+            }
+            let iocon = IOCON::from_bits_retain(self.transfer(address, banked, None)?);
+            if iocon.contains(IOCON::BANK) {
+                debug!("Device at {address} is in the banked layout");
+                self.transfer(address, banked, Some((IOCON::BANK | IOCON::HAEN).bits()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the register at the physical `register_address` of the device at `address`
+    /// or, given `data`, write it. Returns the byte read.
+    fn transfer(
+        &self,
+        address: HardwareAddress,
+        register_address: u8,
+        data: Option<u8>,
+    ) -> Result<u8> {
+        let command = match data {
+            Some(_) => SpiCommand::Write,
+            None => SpiCommand::Read,
+        };
+        let write_buffer = [
+            address.control_byte(command),
+            register_address,
+            data.unwrap_or(0),
+        ];
+        let mut read_buffer = [0u8; 3];
+        let read_length = self
+            .transport
+            .lock()
+            .transfer(&mut read_buffer, &write_buffer)?;
+        if read_length != write_buffer.len() {
+            return Err(Mcp23s17Error::UnexpectedReadLength(read_length));
+        }
+        Ok(read_buffer[2])
+    }
+
+    /// Returns an [`Mcp23s17`] for the device at the hardware `address`.
+    ///
+    /// Each address may only be in use once: if it is, `Mcp23s17Bus::get()` returns
+    /// `Err(`[`Mcp23s17Error::HardwareAddressInUse`]`)`. The address is released once
+    /// the `Mcp23s17` and all of its pins have gone out of scope.
+    ///
+    /// The `Mcp23s17` keeps [`IOCON::HAEN`] set in every write to `IOCON`, so may be
+    /// configured with [`Mcp23s17::configure()`] or reset with
+    /// [`Mcp23s17::reset_to_por_defaults()`] without the other devices on the bus
+    /// answering to its address.
+    pub fn get(&self, address: HardwareAddress) -> Result<Mcp23s17<SharedTransport<T>>> {
+        let index = usize::from(u8::from(address));
+        {
+            let mut addresses_taken = self.addresses_taken.lock();
+            if addresses_taken[index] {
+                return Err(Mcp23s17Error::HardwareAddressInUse(address.into()));
+            }
+            addresses_taken[index] = true;
+        }
+        let transport = SharedTransport {
+            transport: self.transport.clone(),
+            index,
+            addresses_taken: self.addresses_taken.clone(),
+        };
+        let mut mcp23s17_state = Mcp23s17State::new(transport, self.spi_bus, address);
+        mcp23s17_state.register_map.hardware_addressed = true;
+        Ok(Mcp23s17::from_state(mcp23s17_state))
+    }
+
+    /// Find the hardware addresses at which devices respond.
+    ///
+    /// Each address not already in use is checked with
+    /// [`Mcp23s17::probe()`][crate::Mcp23s17::probe], which restores the register that
+    /// it uses. The addresses in use, having been handed out by
+    /// [`Mcp23s17Bus::get()`], are included without being probed.
+    pub fn discover(&self) -> Result<Vec<HardwareAddress>> {
+        let mut addresses = Vec::new();
+        for address in 0..=HardwareAddress::MAX_HARDWARE_ADDRESS {
+            let address = HardwareAddress(address);
+            match self.get(address) {
+                Ok(mcp23s17) => match mcp23s17.probe() {
+                    Ok(()) => addresses.push(address),
+                    Err(Mcp23s17Error::DeviceNotResponding) => (),
+                    Err(error) => return Err(error),
+                },
+                Err(Mcp23s17Error::HardwareAddressInUse(_)) => addresses.push(address),
+                Err(error) => return Err(error),
+            }
+        }
+        debug!("Discovered devices at {addresses:?}");
+        Ok(addresses)
+    }
+
+    /// Get the SPI bus that the devices are accessed over, if known.
+    pub fn get_spi_bus(&self) -> Option<SpiBus> {
+        self.spi_bus
+    }
+}
+
+/// The share of an [`Mcp23s17Bus`]'s transport used by one of its devices.
+///
+/// Holding it keeps the device's hardware address in use.
+#[derive(Debug)]
+pub struct SharedTransport<T: Transport = Spi> {
+    transport: Shared<T>,
+    index: usize,
+    addresses_taken: AddressesTaken,
+}
+
+impl<T: Transport> Transport for SharedTransport<T> {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        self.transport.lock().transfer(read_buffer, write_buffer)
+    }
+}
+
+impl<T: Transport> Drop for SharedTransport<T> {
+    fn drop(&mut self) {
+        self.addresses_taken.lock()[self.index] = false;
+    }
+}
@@ -0,0 +1,157 @@
+//! Declarative configuration of the device applied when it is created.
+//!
+//! Rather than writing raw [`IOCON`] bits to the [`RegisterAddress::IOCON`] register,
+//! an [`Mcp23s17Config`] is built with typed options and then applied (and verified)
+//! with [`Mcp23s17::new_with_config()`][crate::Mcp23s17::new_with_config] or
+//! [`Mcp23s17::configure()`][crate::Mcp23s17::configure].
+//!
+//! ```no_run
+//! use rppal_mcp23s17::{ChipSelect, HardwareAddress, Level, Mcp23s17, Mcp23s17Config, SpiBus, SpiMode};
+//!
+//! let config = Mcp23s17Config::builder()
+//!     .mirror(true)
+//!     .hardware_address_enable(true)
+//!     .interrupt_polarity(Level::High)
+//!     .probe(true)
+//!     .build();
+//! let mcp23s17 = Mcp23s17::new_with_config(
+//!     HardwareAddress::new(2).expect("Invalid hardware address"),
+//!     SpiBus::Spi0,
+//!     ChipSelect::Cs0,
+//!     100_000,
+//!     SpiMode::Mode0,
+//!     config,
+//! )
+//! .expect("Failed to create MCP23S17");
+//! ```
+//!
+//! [`RegisterAddress::IOCON`]: crate::RegisterAddress::IOCON
+
+use super::{IOCON, Level};
+
+/// The configuration of the MCP23S17, as held in its `IOCON` register, and how it is
+/// applied.
+///
+/// The default configuration matches the device's power-on-reset state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mcp23s17Config {
+    iocon: IOCON,
+    probe: bool,
+    reset: bool,
+}
+
+impl Mcp23s17Config {
+    /// Start building a configuration from the power-on-reset defaults.
+    pub fn builder() -> Mcp23s17ConfigBuilder {
+        Mcp23s17ConfigBuilder::default()
+    }
+
+    /// The value of the `IOCON` register for this configuration.
+    pub fn iocon(&self) -> IOCON {
+        self.iocon
+    }
+
+    /// Whether the device is probed before the configuration is applied.
+    pub fn probe(&self) -> bool {
+        self.probe
+    }
+
+    /// Whether the device is reset to its power-on defaults before the configuration
+    /// is applied.
+    pub fn reset(&self) -> bool {
+        self.reset
+    }
+}
+
+/// Builder for an [`Mcp23s17Config`].
+///
+/// Each `IOCON` option corresponds to one of the [`IOCON`] bits and defaults to the
+/// power-on-reset state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Mcp23s17ConfigBuilder {
+    iocon: IOCON,
+    probe: bool,
+    reset: bool,
+}
+
+impl Mcp23s17ConfigBuilder {
+    /// Separate the registers of each port into different banks ([`IOCON::BANK`]).
+    /// Defaults to `false`.
+    ///
+    /// See [`BankMode`][crate::BankMode].
+    pub fn bank(self, bank: bool) -> Self {
+        self.set(IOCON::BANK, bank)
+    }
+
+    /// Internally connect the `INTA` and `INTB` outputs ([`IOCON::MIRROR`]). Defaults
+    /// to `false`.
+    pub fn mirror(self, mirror: bool) -> Self {
+        self.set(IOCON::MIRROR, mirror)
+    }
+
+    /// Increment the address pointer after each byte of a transfer (the inverse of
+    /// [`IOCON::SEQOP`]). Defaults to `true`, which is needed by the burst and 16-bit
+    /// operations.
+    pub fn sequential_operation(self, sequential_operation: bool) -> Self {
+        self.set(IOCON::SEQOP, !sequential_operation)
+    }
+
+    /// Control the slew rate of the I2C `SDA` output (the inverse of
+    /// [`IOCON::DISSLW`]). Defaults to `true`.
+    pub fn slew_rate_control(self, slew_rate_control: bool) -> Self {
+        self.set(IOCON::DISSLW, !slew_rate_control)
+    }
+
+    /// Enable the hardware address pins ([`IOCON::HAEN`]). Defaults to `false`.
+    pub fn hardware_address_enable(self, hardware_address_enable: bool) -> Self {
+        self.set(IOCON::HAEN, hardware_address_enable)
+    }
+
+    /// Make the `INT` outputs open-drain ([`IOCON::ODR`]), which overrides the
+    /// interrupt polarity. Defaults to `false`.
+    pub fn open_drain(self, open_drain: bool) -> Self {
+        self.set(IOCON::ODR, open_drain)
+    }
+
+    /// Set the active level of the `INT` outputs ([`IOCON::INTPOL`]). Defaults to
+    /// [`Level::Low`].
+    pub fn interrupt_polarity(self, polarity: Level) -> Self {
+        self.set(IOCON::INTPOL, polarity == Level::High)
+    }
+
+    /// Check that the device is present with
+    /// [`Mcp23s17::probe()`][crate::Mcp23s17::probe] before applying the
+    /// configuration. Defaults to `false`.
+    pub fn probe(mut self, probe: bool) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Reset all the registers to their power-on defaults with
+    /// [`Mcp23s17::reset_to_por_defaults()`][crate::Mcp23s17::reset_to_por_defaults]
+    /// before applying the configuration, rather than carrying on from the state that
+    /// the device was left in. Defaults to `false`.
+    pub fn reset(mut self, reset: bool) -> Self {
+        self.reset = reset;
+        self
+    }
+
+    /// Build the configuration.
+    ///
+    /// Every combination of the options can be built. Whether the device can honour
+    /// the configuration is only known when it is applied, so that is when any
+    /// [`UnsupportedConfiguration`][crate::Mcp23s17Error::UnsupportedConfiguration] is
+    /// reported (_e.g._ [`IOCON::MIRROR`] on a single-port device).
+    pub fn build(self) -> Mcp23s17Config {
+        Mcp23s17Config {
+            iocon: self.iocon,
+            probe: self.probe,
+            reset: self.reset,
+        }
+    }
+
+    fn set(mut self, flag: IOCON, value: bool) -> Self {
+        self.iocon.set(flag, value);
+        self
+    }
+}
@@ -0,0 +1,372 @@
+//! Servicing the interrupts raised by the MCP23S17.
+//!
+//! When an [`InputPin`][crate::InputPin] with interrupts enabled (see
+//! [`InputPin::set_interrupt_mode()`][crate::InputPin::set_interrupt_mode]) triggers, the
+//! device records which pin caused the interrupt in the interrupt flag register (`INTF`)
+//! and the state of the port at that instant in the interrupt capture register
+//! (`INTCAP`). [`Mcp23s17::take_interrupts()`][crate::Mcp23s17::take_interrupts] reads
+//! and decodes those registers into [`InterruptEvent`]s.
+//!
+//! Alternatively, a callback can be registered for each pin with
+//! [`InputPin::set_interrupt_callback()`][crate::InputPin::set_interrupt_callback] and
+//! then [`Mcp23s17::service_interrupts()`][crate::Mcp23s17::service_interrupts]
+//! dispatches each event to the callback of the pin that raised it.
+//!
+//! Rather than polling the device, the MCP23S17's `INTA`/`INTB` outputs can be wired to
+//! host inputs (_e.g._ Raspberry Pi GPIO pins) that implement [`InterruptLine`]. Once
+//! connected with
+//! [`Mcp23s17::connect_interrupt_lines()`][crate::Mcp23s17::connect_interrupt_lines],
+//! [`InterruptLines::wait_for_interrupts()`] blocks until the device raises an interrupt
+//! and then services it, whilst [`InterruptLines::service_until()`] carries on servicing
+//! each interrupt as it is raised.
+//!
+//! Applications that prefer message passing can use
+//! [`Mcp23s17::subscribe()`][crate::Mcp23s17::subscribe] to receive every event as a
+//! [`TimestampedInterruptEvent`] over a channel.
+
+use std::{
+    fmt,
+    sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use rppal::{
+    gpio::{Bias, Gpio, Trigger},
+    spi::Spi,
+};
+
+use super::{
+    IOCON, Level, Mcp23s17Error, Mcp23s17State, Port, RegisterAddress, Result, Shared, Transport,
+};
+
+/// An interrupt raised by a pin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptEvent {
+    /// The GPIO port of the pin that raised the interrupt.
+    pub port: Port,
+    /// The number (0-7) of the pin that raised the interrupt.
+    pub pin: u8,
+    /// The level of the pin captured when the interrupt was raised.
+    pub level: Level,
+}
+
+impl InterruptEvent {
+    /// Decode the interrupt flag and capture registers of ports A and B into the
+    /// events, ordered by port and then pin number.
+    pub(crate) fn decode(flags: [u8; 2], captures: [u8; 2]) -> Vec<InterruptEvent> {
+        [Port::GpioA, Port::GpioB]
+            .into_iter()
+            .zip(flags.into_iter().zip(captures))
+            .flat_map(|(port, (flag, capture))| {
+                (0..8u8)
+                    .filter(move |pin| flag & (0x01 << pin) != 0)
+                    .map(move |pin| InterruptEvent {
+                        port,
+                        pin,
+                        level: Level::from(capture & (0x01 << pin)),
+                    })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for InterruptEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pin {} {}", self.port, self.pin, self.level)
+    }
+}
+
+/// An [`InterruptEvent`] along with when it was read from the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimestampedInterruptEvent {
+    /// When the interrupt registers were read.
+    pub timestamp: Instant,
+    /// The interrupt.
+    pub event: InterruptEvent,
+}
+
+/// Sends the interrupt events to each of the subscribers' channels.
+///
+/// The channels are bounded so a subscriber that does not keep up would lose events.
+/// Rather than losing them silently, the number of events that could not be sent is
+/// counted.
+#[derive(Debug, Default)]
+pub(crate) struct InterruptPublisher {
+    subscribers: Vec<SyncSender<TimestampedInterruptEvent>>,
+    overflow_count: u64,
+}
+
+impl InterruptPublisher {
+    /// Add a subscriber whose channel can hold up to `capacity` events, and at least
+    /// one: a channel with no room would lose every event.
+    pub(crate) fn subscribe(&mut self, capacity: usize) -> Receiver<TimestampedInterruptEvent> {
+        let (sender, receiver) = sync_channel(capacity.max(1));
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Send the `events` to all the subscribers, dropping any that have gone away.
+    pub(crate) fn publish(&mut self, events: &[InterruptEvent]) {
+        if events.is_empty() || self.subscribers.is_empty() {
+            return;
+        }
+        let timestamp = Instant::now();
+        let mut overflow_count = 0;
+        self.subscribers.retain(|subscriber| {
+            for &event in events {
+                match subscriber.try_send(TimestampedInterruptEvent { timestamp, event }) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Interrupt subscriber full: lost {event}");
+                        overflow_count += 1;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            true
+        });
+        self.overflow_count += overflow_count;
+    }
+
+    /// The total number of events that could not be sent to a subscriber.
+    pub(crate) fn overflow_count(&self) -> u64 {
+        self.overflow_count
+    }
+}
+
+/// A callback for the interrupts raised by a pin, called with the captured level.
+#[cfg(not(feature = "sync"))]
+pub(crate) type InterruptCallback = Box<dyn FnMut(Level) + 'static>;
+
+/// A callback for the interrupts raised by a pin, called with the captured level.
+#[cfg(feature = "sync")]
+pub(crate) type InterruptCallback = Box<dyn FnMut(Level) + Send + 'static>;
+
+/// The table of callbacks registered for each pin.
+///
+/// A callback is taken out of the table whilst it is being called so that the callback
+/// is free to use the device and its pins. Each slot has a generation count that is
+/// bumped whenever the slot is changed so that a callback is only put back if its pin
+/// has not been released, or given a new callback, in the meantime.
+#[derive(Default)]
+pub(crate) struct InterruptDispatcher {
+    callbacks: [[Option<InterruptCallback>; 8]; 2],
+    generations: [[u32; 8]; 2],
+}
+
+impl InterruptDispatcher {
+    /// Set (or, with `None`, remove) the callback for a pin.
+    pub(crate) fn set(&mut self, port: Port, pin: u8, callback: Option<InterruptCallback>) {
+        let (port, pin) = (Self::port_index(port), pin as usize);
+        self.callbacks[port][pin] = callback;
+        self.generations[port][pin] = self.generations[port][pin].wrapping_add(1);
+    }
+
+    /// Take the callback for a pin out of the table ready to call it.
+    pub(crate) fn take(&mut self, port: Port, pin: u8) -> Option<(InterruptCallback, u32)> {
+        let (port, pin) = (Self::port_index(port), pin as usize);
+        self.callbacks[port][pin]
+            .take()
+            .map(|callback| (callback, self.generations[port][pin]))
+    }
+
+    /// Put a callback back in the table after calling it, unless the pin's slot has
+    /// changed since it was taken.
+    pub(crate) fn restore(
+        &mut self,
+        port: Port,
+        pin: u8,
+        callback: InterruptCallback,
+        generation: u32,
+    ) {
+        let (port, pin) = (Self::port_index(port), pin as usize);
+        if self.generations[port][pin] == generation {
+            self.callbacks[port][pin] = Some(callback);
+        }
+    }
+
+    fn port_index(port: Port) -> usize {
+        match port {
+            Port::GpioA => 0,
+            Port::GpioB => 1,
+        }
+    }
+}
+
+impl fmt::Debug for InterruptDispatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registered = self
+            .callbacks
+            .each_ref()
+            .map(|callbacks| callbacks.each_ref().map(|callback| callback.is_some()));
+        f.debug_struct("InterruptDispatcher")
+            .field("registered", &registered)
+            .finish()
+    }
+}
+
+/// Service the pending interrupts, dispatching them to the registered callbacks.
+pub(crate) fn service_interrupts<T: Transport>(
+    mcp23s17_state: &Shared<Mcp23s17State<T>>,
+) -> Result<Vec<InterruptEvent>> {
+    let events = mcp23s17_state.lock().take_interrupts()?;
+    for event in &events {
+        // Don't hold the borrow on the state whilst the callback runs.
+        let callback = mcp23s17_state
+            .lock()
+            .interrupt_dispatcher
+            .take(event.port, event.pin);
+        if let Some((mut callback, generation)) = callback {
+            debug!("Dispatch interrupt {event}");
+            callback(event.level);
+            mcp23s17_state
+                .lock()
+                .interrupt_dispatcher
+                .restore(event.port, event.pin, callback, generation);
+        }
+    }
+    Ok(events)
+}
+
+/// A host input wired to one of the MCP23S17's interrupt outputs (`INTA` or `INTB`).
+///
+/// Implemented for the Raspberry Pi's [`rppal::gpio::InputPin`].
+pub trait InterruptLine: Sized {
+    /// Configure the input to be triggered by the interrupt output becoming active:
+    /// on the rising edge if the output is `active_high`, otherwise on the falling edge.
+    /// The input's pull should hold the line inactive.
+    fn configure(&mut self, active_high: bool) -> Result<()>;
+
+    /// Read whether the input is at a high level.
+    fn is_high(&mut self) -> Result<bool>;
+
+    /// Block until any of the `lines` is triggered or until the `timeout` (if any)
+    /// expires, returning whether a trigger occurred.
+    ///
+    /// Spurious triggers are harmless, but a trigger must not be missed because it
+    /// happened before the wait started.
+    fn wait(lines: &mut [Self], timeout: Option<Duration>) -> Result<bool>;
+}
+
+impl InterruptLine for rppal::gpio::InputPin {
+    fn configure(&mut self, active_high: bool) -> Result<()> {
+        if active_high {
+            self.set_bias(Bias::PullDown);
+            self.set_interrupt(Trigger::RisingEdge, None)?;
+        } else {
+            self.set_bias(Bias::PullUp);
+            self.set_interrupt(Trigger::FallingEdge, None)?;
+        }
+        Ok(())
+    }
+
+    fn is_high(&mut self) -> Result<bool> {
+        Ok(rppal::gpio::InputPin::is_high(self))
+    }
+
+    fn wait(lines: &mut [Self], timeout: Option<Duration>) -> Result<bool> {
+        // Don't reset so that any trigger since the last wait is reported.
+        match lines {
+            [line] => Ok(line.poll_interrupt(false, timeout)?.is_some()),
+            lines => {
+                let lines: Vec<&rppal::gpio::InputPin> = lines.iter().collect();
+                Ok(Gpio::new()?
+                    .poll_interrupts(&lines, false, timeout)?
+                    .is_some())
+            }
+        }
+    }
+}
+
+/// The host inputs wired to the MCP23S17's interrupt outputs.
+///
+/// Created by
+/// [`Mcp23s17::connect_interrupt_lines()`][crate::Mcp23s17::connect_interrupt_lines].
+#[derive(Debug)]
+pub struct InterruptLines<L: InterruptLine, T: Transport = Spi> {
+    lines: Vec<L>,
+    /// Whether the interrupt outputs are active-high.
+    active_high: bool,
+    mcp23s17_state: Shared<Mcp23s17State<T>>,
+}
+
+impl<L: InterruptLine, T: Transport> InterruptLines<L, T> {
+    /// Configure the `lines` to match the device's `IOCON` register.
+    pub(crate) fn new(mut lines: Vec<L>, mcp23s17_state: Shared<Mcp23s17State<T>>) -> Result<Self> {
+        if lines.is_empty() {
+            return Err(Mcp23s17Error::NoInterruptLine);
+        }
+        let iocon = IOCON::from_bits_retain(mcp23s17_state.lock().read(RegisterAddress::IOCON)?);
+        // The open-drain output is always active-low.
+        let active_high = iocon.contains(IOCON::INTPOL) && !iocon.contains(IOCON::ODR);
+        debug!(
+            "Connect {} interrupt line(s): active_high={active_high}",
+            lines.len()
+        );
+        for line in lines.iter_mut() {
+            line.configure(active_high)?;
+        }
+        Ok(InterruptLines {
+            lines,
+            active_high,
+            mcp23s17_state,
+        })
+    }
+
+    /// Wait for the MCP23S17 to raise an interrupt, or until the `timeout` (if any)
+    /// expires, and then service it.
+    ///
+    /// The interrupts are serviced as for
+    /// [`Mcp23s17::service_interrupts()`][crate::Mcp23s17::service_interrupts], calling
+    /// any registered callbacks, and the events are returned. If an interrupt is
+    /// already active, it is serviced without waiting. An empty list is returned if the
+    /// wait timed out.
+    pub fn wait_for_interrupts(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<InterruptEvent>> {
+        if !self.is_active()? && !L::wait(&mut self.lines, timeout)? {
+            return Ok(Vec::new());
+        }
+        service_interrupts(&self.mcp23s17_state)
+    }
+
+    /// Service the interrupts each time the MCP23S17 raises one, blocking the calling
+    /// thread until `stop` returns `true`.
+    ///
+    /// Each interrupt is waited for and serviced as for
+    /// [`InterruptLines::wait_for_interrupts()`], calling any registered callbacks,
+    /// and then `stop` is called with the events. Returns early if waiting for, or
+    /// servicing, an interrupt fails.
+    ///
+    /// To service the interrupts in the background, enable the `sync` feature and
+    /// move the `InterruptLines` (whose host inputs must then be [`Send`]) into a
+    /// thread that calls this method.
+    pub fn service_until<F>(&mut self, mut stop: F) -> Result<()>
+    where
+        F: FnMut(&[InterruptEvent]) -> bool,
+    {
+        loop {
+            let events = self.wait_for_interrupts(None)?;
+            if stop(&events) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether any of the interrupt outputs is currently active.
+    pub fn is_active(&mut self) -> Result<bool> {
+        for line in self.lines.iter_mut() {
+            if line.is_high()? == self.active_high {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Consume the `InterruptLines`, returning the host inputs.
+    pub fn release(self) -> Vec<L> {
+        self.lines
+    }
+}
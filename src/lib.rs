@@ -1,26 +1,67 @@
 #![deny(missing_docs)]
 #![doc = include_str!("../README.md")]
 
-use std::{cell::RefCell, fmt, rc::Rc, result};
+use std::{fmt, result, sync::mpsc::Receiver};
 
 use bitflags::bitflags;
 use log::{debug, error};
-use rppal::spi::SlaveSelect;
+use rppal::{
+    i2c::I2c,
+    spi::{SlaveSelect, Spi},
+};
 
 /// Re-exports of [rppal::spi] module APIs used on this crate's APIs. Renamed to make
 /// sure that the intended usage is clear.
 pub use rppal::spi::{Bus as SpiBus, Mode as SpiMode};
 
-// Run with mock hardware in testing.
 #[cfg(any(test, feature = "mockspi"))]
-use mock_spi::MockSpi;
-#[cfg(not(any(test, feature = "mockspi")))]
-use rppal::spi::Spi;
+use mock_spi::{MockI2c, MockSpi};
 
 use thiserror::Error;
 
+pub mod bus;
+pub use self::bus::{Mcp23s17Bus, SharedTransport};
+
+mod shared;
+pub use self::shared::MaybeSend;
+use self::shared::Shared;
+
+mod register_map;
+use self::register_map::RegisterMap;
+
+pub mod config;
+pub use self::config::{Mcp23s17Config, Mcp23s17ConfigBuilder};
+
+pub mod interrupt;
+use self::interrupt::{InterruptDispatcher, InterruptPublisher};
+pub use self::interrupt::{
+    InterruptEvent, InterruptLine, InterruptLines, TimestampedInterruptEvent,
+};
+
 pub mod pin;
-pub use self::pin::{InputPin, InterruptMode, Level, OutputPin, Pin};
+use self::pin::PortConfiguration;
+pub use self::pin::{
+    AttachedPin, InputPin, InputPinMode, InterruptMode, Level, OutputPin, Pin, PinGroup,
+};
+
+pub mod port_bus;
+pub use self::port_bus::PortBus;
+
+#[cfg(feature = "process-lock")]
+pub mod process_lock;
+#[cfg(feature = "process-lock")]
+pub use self::process_lock::ProcessLock;
+
+#[cfg(feature = "embedded-hal-async")]
+pub mod asynch;
+
+pub mod mcp23s08;
+pub use self::mcp23s08::{Mcp23s08, Mcp23008};
+
+pub mod transport;
+#[cfg(feature = "embedded-hal")]
+pub use self::transport::SpiDeviceTransport;
+pub use self::transport::{I2cBus, I2cTransport, Transport};
 
 //--------------------------------------------------------------------------------------
 /// The hardware address of the device - three bits.
@@ -43,6 +84,20 @@ impl HardwareAddress {
             Err(Mcp23s17Error::HardwareAddressBoundsError(address))
         }
     }
+
+    /// Calculate the control byte to use in a message to the device at this address.
+    ///
+    /// The client address contains four fixed bits and three user-defined hardware
+    /// address bits (if enabled via `IOCON::HAEN`) (pins A2, A1 and A0) with the
+    /// read/write bit filling out the control byte.
+    fn control_byte(self, command: SpiCommand) -> u8 {
+        let control_byte = 0x40 | self.0 << 1 | command as u8;
+        debug!(
+            "ControlByte: 0x{:02x} (Command='{:?}' address={:?})",
+            control_byte, command, self.0
+        );
+        control_byte
+    }
 }
 
 impl fmt::Display for HardwareAddress {
@@ -86,9 +141,10 @@ impl fmt::Display for SpiCommand {
 //--------------------------------------------------------------------------------------
 /// The register address within the device.
 ///
-/// Note that this follows the "interleaved" format for the register addresses so that
-/// the [`IOCON::BANK`] bit of [`IOCON`][`RegisterAddress::IOCON`] register must be set
-/// to 0 ([`IOCON::BANK_OFF`]).
+/// The values follow the "interleaved" format for the register addresses
+/// ([`IOCON::BANK_OFF`]). The driver maps each register to its physical address
+/// according to the device's [`BankMode`], so the same names are used whether or not
+/// the [`IOCON::BANK`] bit is set.
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(u8)]
@@ -143,6 +199,21 @@ pub enum RegisterAddress {
 impl RegisterAddress {
     /// Total number of registers defined within the MCP23S17.
     pub const LENGTH: usize = 0x16;
+
+    /// Whether the register only changes when written by the host, so can be held in
+    /// the shadow register cache. The `GPIO`, `INTF` and `INTCAP` registers follow the
+    /// pins so must always be read from the device.
+    pub(crate) fn is_cacheable(self) -> bool {
+        !matches!(
+            self,
+            RegisterAddress::INTFA
+                | RegisterAddress::INTFB
+                | RegisterAddress::INTCAPA
+                | RegisterAddress::INTCAPB
+                | RegisterAddress::GPIOA
+                | RegisterAddress::GPIOB
+        )
+    }
 }
 
 impl From<RegisterAddress> for u8 {
@@ -212,16 +283,121 @@ impl fmt::Display for RegisterAddress {
     }
 }
 
+/// How the [`RegisterAddress`]es map onto the physical register addresses of a device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum RegisterLayout {
+    /// The 16-bit devices with [`IOCON::BANK_OFF`]: the `A` and `B` registers are
+    /// interleaved, which is exactly how [`RegisterAddress`] is defined.
+    #[default]
+    Interleaved,
+    /// The 16-bit devices with [`IOCON::BANK_ON`]: the `A` registers are packed
+    /// together from IODIRA=0x00 through to OLATA=0x0A and the `B` registers from
+    /// IODIRB=0x10 through to OLATB=0x1A.
+    Banked,
+    /// The 8-bit devices (_e.g._ MCP23S08) that only have the `A` registers, which are
+    /// packed together from IODIR=0x00 through to OLAT=0x0A.
+    SinglePort,
+}
+
+impl RegisterLayout {
+    /// The registers of a single-port device in the order of their physical addresses.
+    const SINGLE_PORT_REGISTERS: [RegisterAddress; 11] = [
+        RegisterAddress::IODIRA,
+        RegisterAddress::IPOLA,
+        RegisterAddress::GPINTENA,
+        RegisterAddress::DEFVALA,
+        RegisterAddress::INTCONA,
+        RegisterAddress::IOCON,
+        RegisterAddress::GPPUA,
+        RegisterAddress::INTFA,
+        RegisterAddress::INTCAPA,
+        RegisterAddress::GPIOA,
+        RegisterAddress::OLATA,
+    ];
+
+    /// Physical address of the `B` registers in the banked layout.
+    const BANK_B: u8 = 0x10;
+
+    /// The layout of a 16-bit device in the `bank_mode`.
+    fn for_bank_mode(bank_mode: BankMode) -> Self {
+        match bank_mode {
+            BankMode::Interleaved => RegisterLayout::Interleaved,
+            BankMode::Segregated => RegisterLayout::Banked,
+        }
+    }
+
+    /// Number of physical register addresses spanned by this layout.
+    pub(crate) fn length(self) -> usize {
+        match self {
+            RegisterLayout::Interleaved => RegisterAddress::LENGTH,
+            RegisterLayout::Banked => usize::from(Self::BANK_B) + Self::SINGLE_PORT_REGISTERS.len(),
+            RegisterLayout::SinglePort => Self::SINGLE_PORT_REGISTERS.len(),
+        }
+    }
+
+    /// The physical address one beyond the last register that can be reached by a
+    /// burst starting at `address`.
+    fn burst_end(self, address: u8) -> usize {
+        match self {
+            RegisterLayout::Banked if address < Self::BANK_B => Self::SINGLE_PORT_REGISTERS.len(),
+            _ => self.length(),
+        }
+    }
+
+    /// Map the `register` to its physical address in this layout.
+    ///
+    /// Returns [`Mcp23s17Error::RegisterAddressBoundsError`] if the register does not
+    /// exist in this layout.
+    pub(crate) fn address(self, register: RegisterAddress) -> Result<u8> {
+        match self {
+            RegisterLayout::Interleaved => Ok(register as u8),
+            RegisterLayout::Banked => {
+                // The `A` register of each interleaved pair is at the even address.
+                let port_a = RegisterAddress::try_from(usize::from(register as u8 & !0x01))?;
+                let offset = RegisterLayout::SinglePort.address(port_a)?;
+                if register as u8 & 0x01 == 0 {
+                    Ok(offset)
+                } else {
+                    Ok(Self::BANK_B + offset)
+                }
+            }
+            RegisterLayout::SinglePort => Self::SINGLE_PORT_REGISTERS
+                .iter()
+                .position(|&r| r == register)
+                .map(|address| address as u8)
+                .ok_or_else(|| {
+                    error!("{register} does not exist on a single-port device");
+                    Mcp23s17Error::RegisterAddressBoundsError
+                }),
+        }
+    }
+
+    /// Map the physical `address` back to the register at that address in this layout.
+    pub(crate) fn register(self, address: u8) -> Result<RegisterAddress> {
+        match self {
+            RegisterLayout::Interleaved => RegisterAddress::try_from(usize::from(address)),
+            RegisterLayout::Banked if address >= Self::BANK_B => {
+                let port_a = RegisterLayout::SinglePort.register(address - Self::BANK_B)?;
+                RegisterAddress::try_from(port_a as usize + 1)
+            }
+            RegisterLayout::Banked | RegisterLayout::SinglePort => Self::SINGLE_PORT_REGISTERS
+                .get(usize::from(address))
+                .copied()
+                .ok_or(Mcp23s17Error::RegisterAddressBoundsError),
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------
 
 bitflags! {
     /// I/O Expander Configuration Register (`IOCON`) bit definitions.
-    #[derive(Debug, PartialEq)]
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
     pub struct IOCON: u8 {
         /// Controls how the registers are addressed:
         ///
         ///   1 = The registers associated with each port are separated into different
-        ///       banks. (*Not currently supported in this library.*)
+        ///       banks. (See [`BankMode`].)
         ///
         ///   0 = The registers are in the same bank (addresses are sequential).
         const BANK = 0b1000_0000;
@@ -276,7 +452,7 @@ bitflags! {
 
 impl IOCON {
     /// The registers associated with each port are separated into different
-    /// banks. (*Not currently supported in this library.*)
+    /// banks.
     pub const BANK_ON: IOCON = IOCON::BANK;
     /// The registers are in the same bank (addresses are interleaved sequentially).
     pub const BANK_OFF: IOCON = IOCON::empty();
@@ -307,7 +483,34 @@ impl IOCON {
     pub const INTPOL_LOW: IOCON = IOCON::empty();
 }
 
+/// How the MCP23S17's registers are arranged, as selected by the [`IOCON::BANK`] bit.
+///
+/// The driver tracks the mode so that each [`RegisterAddress`] is accessed at the
+/// right physical address. The mode changes whenever `IOCON` is written by the driver
+/// and can be switched with [`Mcp23s17::set_bank_mode()`]. If the device was left in
+/// [`BankMode::Segregated`] by something else (the mode survives anything but a
+/// power-on-reset) then use [`Mcp23s17::assume_bank_mode()`] to tell the driver.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BankMode {
+    /// `BANK=0`: the `A` and `B` registers are interleaved (the power-on default).
+    #[default]
+    Interleaved,
+    /// `BANK=1`: the `A` and `B` registers are separated into different banks.
+    Segregated,
+}
+
+impl fmt::Display for BankMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BankMode::Interleaved => fmt::Display::fmt("Interleaved (BANK=0)", f),
+            BankMode::Segregated => fmt::Display::fmt("Segregated (BANK=1)", f),
+        }
+    }
+}
+
 /// The MCP23S17 has two GPIO ports, GPIOA and GPIOB.
+///
+/// The 8-bit [`Mcp23s08`] only has the one port, which is treated as GPIOA.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Port {
     /// GPIO A
@@ -419,13 +622,63 @@ pub enum Mcp23s17Error {
         source: rppal::spi::Error,
     },
 
+    /// Errors from the Raspberry Pi [GPIO][rppal::gpio::Gpio] used as an
+    /// [`InterruptLine`].
+    #[error("GPIO error")]
+    GpioError {
+        /// Underlying error source.
+        #[from]
+        source: rppal::gpio::Error,
+    },
+
+    /// Errors from the [I2C][rppal::i2c::I2c].
+    #[error("I2C error")]
+    I2cError {
+        /// Underlying error source.
+        #[from]
+        source: rppal::i2c::Error,
+    },
+
+    /// Errors from an [`embedded_hal::spi::SpiDevice`] used through a
+    /// [`SpiDeviceTransport`].
+    #[cfg(feature = "embedded-hal")]
+    #[error("SPI device error: {0}")]
+    SpiDeviceError(embedded_hal::spi::ErrorKind),
+
+    /// Errors from the host GPIO connected to the MCP23S17's `INT` output.
+    #[cfg(feature = "embedded-hal")]
+    #[error("Interrupt line error: {0}")]
+    InterruptLineError(embedded_hal::digital::ErrorKind),
+
+    /// Errors from the lock file of a [`ProcessLock`].
+    #[cfg(feature = "process-lock")]
+    #[error("Process lock error")]
+    ProcessLockError {
+        /// Underlying error source.
+        #[from]
+        source: std::io::Error,
+    },
+
+    /// Errors from a user-supplied [`Transport`].
+    #[error("Transport error")]
+    TransportError {
+        /// Underlying error source.
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// An attempt was made to connect an empty set of [`InterruptLine`]s.
+    #[error("No interrupt line connected")]
+    NoInterruptLine,
+
     /// Attempt to access an MCP23S17 beyond the hardware address range
-    /// (0 - [`HardwareAddress::MAX_HARDWARE_ADDRESS`]).
+    /// (0 - [`HardwareAddress::MAX_HARDWARE_ADDRESS`]) or an MCP23S08 beyond its
+    /// narrower range (0 - [`Mcp23s08::MAX_HARDWARE_ADDRESS`]).
     #[error("Hardware address out of range")]
     HardwareAddressBoundsError(u8),
 
     /// Attempt to access an MCP23S17 register beyond the valid set defined in
-    /// [`RegisterAddress`].
+    /// [`RegisterAddress`], or one that the device does not have (_e.g._ any of the
+    /// port B registers on an MCP23S08).
     #[error("Register address out of range")]
     RegisterAddressBoundsError,
 
@@ -434,6 +687,11 @@ pub enum Mcp23s17Error {
     #[error("Unexpected number of bytes read")]
     UnexpectedReadLength(usize),
 
+    /// A [`PinGroup`] was created without any pins, or with pins from more than one
+    /// device.
+    #[error("Pin group is empty or spans devices")]
+    InvalidPinGroup,
+
     /// Either a [`Pin`] was requested beyond the width of the byte-wide GPIO port or
     /// the [`Pin`] has already been taken.
     #[error("Pin out of range or already in use")]
@@ -443,36 +701,74 @@ pub enum Mcp23s17Error {
     /// than 7) bit number.
     #[error("Specified bit is out of range 0-7")]
     RegisterBitBoundsError(u8),
+
+    /// The device did not respond: a pattern written to one of its registers did not
+    /// read back, as happens when no device is fitted.
+    #[error("Device not responding")]
+    DeviceNotResponding,
+
+    /// A device was requested from an [`Mcp23s17Bus`] at a hardware address that is
+    /// already in use.
+    #[error("Hardware address {0} is already in use on the bus")]
+    HardwareAddressInUse(u8),
+
+    /// An [`Mcp23s17Config`] asked for something that the driver or device does not
+    /// support.
+    #[error("Unsupported configuration: {0}")]
+    UnsupportedConfiguration(&'static str),
+
+    /// The `IOCON` register did not read back as written when applying an
+    /// [`Mcp23s17Config`].
+    #[error("Configuration not applied (wrote 0x{written:02x}, read 0x{read:02x})")]
+    ConfigurationNotApplied {
+        /// The value written to `IOCON`.
+        written: u8,
+        /// The value read back from `IOCON`.
+        read: u8,
+    },
 }
 
 /// Convenient wrapper for Result types can have [`Mcp23s17Error`]s.
 pub type Result<T> = result::Result<T, Mcp23s17Error>;
 
+/// Allow [`Mcp23s17Error`] to be reported through the `embedded_hal::digital` traits
+/// implemented by the [`Pin`] types.
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::digital::Error for Mcp23s17Error {
+    fn kind(&self) -> embedded_hal::digital::ErrorKind {
+        embedded_hal::digital::ErrorKind::Other
+    }
+}
+
 /// Struct to represent the state of an MCP23S17 I/O Expander.
 ///
 /// This is separated from the `Mcp23s17` itself so that the state can be shared between
 /// various `Pin` objects etc.
-///
-/// In testing environments this uses mocked hardware.
 #[derive(Debug)]
-struct Mcp23s17State {
-    #[cfg(not(any(test, feature = "mockspi")))]
-    spi: Spi,
-
-    #[cfg(any(test, feature = "mockspi"))]
-    spi: MockSpi,
+struct Mcp23s17State<T: Transport> {
+    /// The transport used to talk to the device.
+    spi: T,
 
-    /// The SPI bus the device is connected to.
-    spi_bus: SpiBus,
+    /// The SPI bus the device is connected to, if known.
+    spi_bus: Option<SpiBus>,
 
     /// The hardware address on the bus.
     address: HardwareAddress,
 
+    /// What is known about the device's registers.
+    register_map: RegisterMap,
+
     /// Keep track of which pins are in use on `GPIOA`.
     gpioa_pins_taken: [bool; 8],
 
     /// Keep track of which pins are in use on `GPIOB`.
     gpiob_pins_taken: [bool; 8],
+
+    /// The callbacks registered for the pins' interrupts.
+    interrupt_dispatcher: InterruptDispatcher,
+
+    /// The subscribers to the interrupt events.
+    interrupt_publisher: InterruptPublisher,
 }
 
 /// A structure that represents an instance of the MCP23S17 I/O expander chip.
@@ -501,48 +797,410 @@ struct Mcp23s17State {
 /// // registers (e.g. IODIRA) are set accordingly.
 /// let pin = mcp23s17.get(Port::GpioA, 4).expect("Failed to get Pin");
 /// ```
+///
+/// The device is accessed through a [`Transport`] which defaults to the RPPAL
+/// [`Spi`]; use [`Mcp23s17::with_transport()`] to supply another.
 #[derive(Debug)]
-pub struct Mcp23s17 {
-    mcp23s17_state: Rc<RefCell<Mcp23s17State>>,
+pub struct Mcp23s17<T: Transport = Spi> {
+    mcp23s17_state: Shared<Mcp23s17State<T>>,
+}
+
+impl Mcp23s17<Spi> {
+    /// Create an MCP23S17 instance accessed over the Raspberry Pi's SPI.
+    ///
+    /// Nothing is written to the device, so it is left in whatever state it was in.
+    /// Use [`Mcp23s17::new_with_config()`] with
+    /// [`Mcp23s17ConfigBuilder::reset()`] to start from the power-on defaults instead.
+    pub fn new(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Self> {
+        let spi = Spi::new(spi_bus, chip_select.into(), spi_clock, spi_mode)?;
+        Ok(Mcp23s17::from_state(Mcp23s17State::new(
+            spi,
+            Some(spi_bus),
+            address,
+        )))
+    }
+
+    /// Create an MCP23S17 instance accessed over the Raspberry Pi's SPI and attach to all
+    /// of its pins as they are already configured, without writing to the device.
+    ///
+    /// This allows a process to take over a running device without glitching its
+    /// outputs. See [`Mcp23s17::attach_pins()`].
+    pub fn attach(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<(Self, Vec<AttachedPin>)> {
+        let mcp23s17 = Mcp23s17::new(address, spi_bus, chip_select, spi_clock, spi_mode)?;
+        let pins = mcp23s17.attach_pins()?;
+        Ok((mcp23s17, pins))
+    }
+
+    /// Find the hardware addresses of the MCP23S17s that share the Raspberry Pi's SPI
+    /// `spi_bus` and `chip_select`.
+    ///
+    /// The hardware address pins of all the devices are enabled and then each address
+    /// is probed. See [`Mcp23s17Bus::with_transport()`] and
+    /// [`Mcp23s17Bus::discover()`].
+    pub fn discover(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Vec<HardwareAddress>> {
+        Mcp23s17Bus::new(spi_bus, chip_select, spi_clock, spi_mode)?.discover()
+    }
+
+    /// Create an MCP23S17 instance accessed over the Raspberry Pi's SPI and apply the
+    /// `config` to it.
+    ///
+    /// See [`Mcp23s17::configure()`].
+    pub fn new_with_config(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+        config: Mcp23s17Config,
+    ) -> Result<Self> {
+        let mcp23s17 = Mcp23s17::new(address, spi_bus, chip_select, spi_clock, spi_mode)?;
+        mcp23s17.configure(&config)?;
+        Ok(mcp23s17)
+    }
+}
+
+/// The MCP23017 is the I2C twin of the MCP23S17.
+///
+/// It has the same register map and pin semantics so it is driven by an [`Mcp23s17`]
+/// whose messages are carried over I2C by an [`I2cTransport`], sharing the same
+/// [`Pin`], [`InputPin`] and [`OutputPin`] types. Note that the MCP23017 hardware
+/// address pins are always enabled, irrespective of [`IOCON::HAEN`].
+///
+/// ```no_run
+/// use rppal_mcp23s17::{HardwareAddress, Mcp23017, Port};
+///
+/// let mcp23017 = Mcp23017::new_i2c(HardwareAddress::new(0).expect("Invalid address"), 1)
+///     .expect("Failed to create MCP23017");
+/// let pin = mcp23017.get(Port::GpioB, 0).expect("Failed to get Pin");
+/// ```
+pub type Mcp23017<I2C = I2c> = Mcp23s17<I2cTransport<I2C>>;
+
+impl Mcp23017<I2c> {
+    /// Create an MCP23017 instance accessed over the Raspberry Pi's I2C bus `i2c_bus`.
+    pub fn new_i2c(address: HardwareAddress, i2c_bus: u8) -> Result<Self> {
+        Ok(Mcp23017::with_i2c(address, I2c::with_bus(i2c_bus)?))
+    }
 }
 
-impl Mcp23s17 {
-    /// Create an MCP23S17 instance with either real or mock hardware.
+impl<I2C: I2cBus> Mcp23017<I2C> {
+    /// Create an MCP23017 instance accessed over a user-supplied [`I2cBus`].
+    pub fn with_i2c(address: HardwareAddress, i2c: I2C) -> Self {
+        Mcp23s17::with_transport(address, I2cTransport::new(i2c))
+    }
+}
+
+#[cfg(any(test, feature = "mockspi"))]
+impl Mcp23017<MockI2c> {
+    /// Create an MCP23017 instance with mock hardware.
+    pub fn new_mock_i2c(address: HardwareAddress) -> Self {
+        Mcp23017::with_i2c(address, MockI2c::new(address))
+    }
+
+    /// In testing environments provide an API to read the MockI2c registers.
+    pub fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
+        self.mcp23s17_state
+            .lock()
+            .spi
+            .i2c
+            .registers
+            .get_mock_data(register)
+    }
+
+    /// In testing environments provide an API to write the MockI2c registers.
+    pub fn set_mock_data(&self, register: RegisterAddress, data: u8) {
+        self.mcp23s17_state
+            .lock()
+            .spi
+            .i2c
+            .registers
+            .set_mock_data(register, data);
+    }
+}
+
+#[cfg(any(test, feature = "mockspi"))]
+impl Mcp23s17<MockSpi> {
+    /// Create an MCP23S17 instance with mock hardware.
     ///
-    /// For now testing always uses mock hardware, which precludes running unit tests on
-    /// real hardware. In practice, that's not much of a practical limitation when running
+    /// Testing always uses mock hardware, which precludes running unit tests on real
+    /// hardware. In practice, that's not much of a practical limitation when running
     /// tests in local or CI cross-compilation environments. Testing on real hardware
     /// focuses on integration testing with the full build.
-    pub fn new(
+    pub fn new_mock(
         address: HardwareAddress,
         spi_bus: SpiBus,
         chip_select: ChipSelect,
         spi_clock: u32,
         spi_mode: SpiMode,
     ) -> Result<Self> {
-        let mcp23s17_state = Mcp23s17State {
-            #[cfg(not(any(test, feature = "mockspi")))]
-            spi: Spi::new(spi_bus, chip_select.into(), spi_clock, spi_mode)?,
-            #[cfg(any(test, feature = "mockspi"))]
-            spi: MockSpi::new(spi_bus, chip_select, spi_clock, spi_mode),
-            spi_bus,
+        let spi = MockSpi::new(spi_bus, chip_select, spi_clock, spi_mode);
+        Ok(Mcp23s17::from_state(Mcp23s17State::new(
+            spi,
+            Some(spi_bus),
             address,
-            gpioa_pins_taken: [false; 8],
-            gpiob_pins_taken: [false; 8],
-        };
-        Ok(Mcp23s17 {
-            mcp23s17_state: Rc::new(RefCell::new(mcp23s17_state)),
-        })
+        )))
+    }
+
+    /// In testing environments provide an API to read the MockSpi registers.
+    pub fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
+        self.mcp23s17_state.lock().spi.get_mock_data(register)
+    }
+
+    /// In testing environments provide an API to write the MockSpi registers.
+    pub fn set_mock_data(&self, register: RegisterAddress, data: u8) {
+        self.mcp23s17_state.lock().spi.set_mock_data(register, data);
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<SPI: embedded_hal::spi::SpiDevice> Mcp23s17<SpiDeviceTransport<SPI>> {
+    /// Create an MCP23S17 instance accessed over an [`embedded_hal::spi::SpiDevice`].
+    ///
+    /// This allows the driver to be used with HALs other than RPPAL (_e.g._
+    /// `linux-embedded-hal`). As with [`Mcp23s17::with_transport()`],
+    /// [`Mcp23s17::get_spi_bus()`] will report `None`.
+    pub fn with_spi_device(address: HardwareAddress, spi: SPI) -> Self {
+        Mcp23s17::with_transport(address, SpiDeviceTransport::new(spi))
+    }
+}
+
+impl<T: Transport> Mcp23s17<T> {
+    /// Create an MCP23S17 instance accessed over a user-supplied [`Transport`].
+    ///
+    /// Since the transport is opaque to the driver, [`Mcp23s17::get_spi_bus()`] will
+    /// report `None` for instances created this way.
+    pub fn with_transport(address: HardwareAddress, transport: T) -> Self {
+        Mcp23s17::from_state(Mcp23s17State::new(transport, None, address))
+    }
+
+    /// Wrap the state shared with the [`Pin`]s.
+    fn from_state(mcp23s17_state: Mcp23s17State<T>) -> Self {
+        Mcp23s17 {
+            mcp23s17_state: Shared::new(mcp23s17_state),
+        }
+    }
+
+    /// Apply the `config` to the device.
+    ///
+    /// The `IOCON` register is written and then read back from the device (even if the
+    /// register cache is enabled) to verify that the configuration was applied,
+    /// returning [`Mcp23s17Error::ConfigurationNotApplied`] if not. If the
+    /// configuration changes the [`BankMode`] then `IOCON` is read back from its new
+    /// address.
+    ///
+    /// If the configuration asks for it, the device is first checked with
+    /// [`Mcp23s17::probe()`] and then reset with [`Mcp23s17::reset_to_por_defaults()`].
+    pub fn configure(&self, config: &Mcp23s17Config) -> Result<()> {
+        self.mcp23s17_state.lock().configure(config)
+    }
+
+    /// Write the power-on-reset values to all the registers: `IODIR` to 0xFF (all pins
+    /// inputs) and the rest to 0x00.
+    ///
+    /// `IOCON` is written first, which returns the device to [`BankMode::Interleaved`]
+    /// but keeps [`IOCON::HAEN`] as it was so that a device sharing its chip select
+    /// still only answers to its own address. Then the interrupts are disabled and the
+    /// pins made inputs before the other registers are written so that no output
+    /// glitches. Finally the `INTCAP` registers are read to clear any pending interrupt.
+    ///
+    /// Any [`Pin`]s in use are not told about the reset so this is best done before
+    /// taking any pins.
+    pub fn reset_to_por_defaults(&self) -> Result<()> {
+        self.mcp23s17_state.lock().reset_to_por_defaults()
+    }
+
+    /// Check that the device is present and responding.
+    ///
+    /// Distinctive patterns are written to `DEFVALA` and read back, before its original
+    /// value is restored. `DEFVALA` only has an effect on the pins of port A with
+    /// interrupts enabled in compare mode ([`InterruptMode::ActiveHigh`] or
+    /// [`InterruptMode::ActiveLow`]), so probing must not be done whilst those are in
+    /// use. Returns [`Mcp23s17Error::DeviceNotResponding`] if the patterns do not read
+    /// back, as when no device is fitted and every read returns 0x00 or 0xFF.
+    pub fn probe(&self) -> Result<()> {
+        self.mcp23s17_state.lock().probe()
+    }
+
+    /// The register arrangement that the driver believes the device is using.
+    pub fn bank_mode(&self) -> BankMode {
+        self.mcp23s17_state.lock().bank_mode()
+    }
+
+    /// Switch the device to the `bank_mode`.
+    ///
+    /// The [`IOCON::BANK`] bit is changed at `IOCON`'s address in the current mode and
+    /// then `IOCON` is read back from its address in the new mode to verify the switch,
+    /// returning [`Mcp23s17Error::ConfigurationNotApplied`] if it did not read back as
+    /// written. The other `IOCON` bits are unchanged.
+    pub fn set_bank_mode(&self, bank_mode: BankMode) -> Result<()> {
+        self.mcp23s17_state.lock().set_bank_mode(bank_mode)
+    }
+
+    /// Tell the driver that the device is already in the `bank_mode` (_e.g._ because
+    /// it was configured by other software) without accessing `IOCON`.
+    ///
+    /// If the register cache is enabled then it is reloaded from the device.
+    pub fn assume_bank_mode(&self, bank_mode: BankMode) -> Result<()> {
+        self.mcp23s17_state.lock().assume_bank_mode(bank_mode)
     }
 
     /// Read a byte from the MCP23S17 register at the address `register`.
     pub fn read(&self, register: RegisterAddress) -> Result<u8> {
-        self.mcp23s17_state.borrow().read(register)
+        self.mcp23s17_state.lock().read(register)
     }
 
     /// Write the byte `data` to the MCP23S17 register at address `register`.
     pub fn write(&self, register: RegisterAddress, data: u8) -> Result<()> {
-        self.mcp23s17_state.borrow().write(register, data)
+        self.mcp23s17_state.lock().write(register, data)
+    }
+
+    /// Read consecutive registers, starting at `start`, into `data` in a single
+    /// transfer.
+    ///
+    /// This relies on the device's address pointer incrementing after each byte so
+    /// sequential operation must be enabled ([`IOCON::SEQOP_ON`], which is the
+    /// power-on default). The registers are consecutive in the device's current
+    /// [`BankMode`]. Returns [`Mcp23s17Error::RegisterAddressBoundsError`] if the burst
+    /// would run beyond the last register ([`RegisterAddress::OLATB`]) or, in
+    /// [`BankMode::Segregated`], beyond the last register of the bank.
+    ///
+    /// With sequential operation disabled ([`IOCON::SEQOP_OFF`]) only the `A` and `B`
+    /// registers of a pair can be accessed together, in [`BankMode::Interleaved`]; any
+    /// longer burst returns [`Mcp23s17Error::UnsupportedConfiguration`] without
+    /// accessing the device.
+    pub fn read_registers(&self, start: RegisterAddress, data: &mut [u8]) -> Result<()> {
+        self.mcp23s17_state.lock().read_registers(start, data)
+    }
+
+    /// Write the bytes in `data` to consecutive registers, starting at `start`, in a
+    /// single transfer.
+    ///
+    /// As for [`Mcp23s17::read_registers()`], sequential operation must be enabled for
+    /// bursts beyond a register pair.
+    /// The burst must not change the [`BankMode`]: use [`Mcp23s17::write()`] or
+    /// [`Mcp23s17::set_bank_mode()`] for that.
+    pub fn write_registers(&self, start: RegisterAddress, data: &[u8]) -> Result<()> {
+        self.mcp23s17_state.lock().write_registers(start, data)
+    }
+
+    /// Read the levels of all 16 GPIO pins at the same instant.
+    ///
+    /// Both `GPIO` registers are read in a single transfer and combined so that GPA0 is
+    /// bit 0 through to GPA7 at bit 7, and GPB0 is bit 8 through to GPB7 at bit 15.
+    ///
+    /// The single transfer works whether or not sequential operation is enabled, since
+    /// with [`IOCON::SEQOP`] set the address pointer toggles between the `A` and `B`
+    /// registers. In [`BankMode::Segregated`] the registers are not adjacent, so they
+    /// are read in two transfers.
+    pub fn read_gpio16(&self) -> Result<u16> {
+        self.mcp23s17_state.lock().read_gpio16()
+    }
+
+    /// Write both output latches (`OLATA` and `OLATB`) in a single transfer.
+    ///
+    /// The bit order of `value` is as for [`Mcp23s17::read_gpio16()`]. In
+    /// [`BankMode::Segregated`] two transfers are needed.
+    pub fn write_olat16(&self, value: u16) -> Result<()> {
+        self.mcp23s17_state.lock().write_olat16(value)
+    }
+
+    /// Change the bits of both output latches that are set in `mask` to the
+    /// corresponding bits of `value`, leaving the other bits unchanged.
+    ///
+    /// The bit order of `mask` and `value` is as for [`Mcp23s17::read_gpio16()`]. The
+    /// latches are read in a single transfer (unless the register cache is enabled) and
+    /// then written in a single transfer so both ports change at the same instant.
+    pub fn modify_olat16(&self, mask: u16, value: u16) -> Result<()> {
+        self.mcp23s17_state.lock().modify_olat16(mask, value)
+    }
+
+    /// Read and decode the pending interrupts.
+    ///
+    /// The interrupt flag (`INTF`) and interrupt capture (`INTCAP`) registers of both
+    /// ports are read in a single sequential transfer (one per port in
+    /// [`BankMode::Segregated`]), returning an [`InterruptEvent`] for each pin that
+    /// raised an interrupt with the level captured at the time. Since `INTCAP` is
+    /// read, the device clears the interrupt condition.
+    ///
+    /// If sequential operation is disabled ([`IOCON::SEQOP_OFF`]) then the flags and
+    /// captures are read in separate transfers instead. The driver knows of the `IOCON`
+    /// value that was last written or read through it, so if another program changed
+    /// `IOCON` then read it first (_e.g._ with [`Mcp23s17::read()`] with the cache
+    /// disabled).
+    pub fn take_interrupts(&self) -> Result<Vec<InterruptEvent>> {
+        self.mcp23s17_state.lock().take_interrupts()
+    }
+
+    /// Service the pending interrupts, calling the callback registered for each pin
+    /// that raised an interrupt with the captured level.
+    ///
+    /// The interrupts are read as for [`Mcp23s17::take_interrupts()`] and all the
+    /// events are returned, including those for pins without a callback. Callbacks are
+    /// registered through [`InputPin::set_interrupt_callback()`] and are called from
+    /// within this method, so they may use the device and its pins.
+    pub fn service_interrupts(&self) -> Result<Vec<InterruptEvent>> {
+        interrupt::service_interrupts(&self.mcp23s17_state)
+    }
+
+    /// Subscribe to the interrupt events.
+    ///
+    /// Every event read from the device, whether by [`Mcp23s17::take_interrupts()`],
+    /// [`Mcp23s17::service_interrupts()`] or [`InterruptLines::wait_for_interrupts()`],
+    /// is sent to the returned channel along with the time that it was read. The
+    /// channel holds up to `capacity` events (at least one, even if `capacity` is 0); if
+    /// it is full, further events are counted by
+    /// [`Mcp23s17::interrupt_overflow_count()`] rather than being silently dropped.
+    ///
+    /// Dropping the receiver ends the subscription.
+    pub fn subscribe(&self, capacity: usize) -> Receiver<TimestampedInterruptEvent> {
+        self.mcp23s17_state
+            .lock()
+            .interrupt_publisher
+            .subscribe(capacity)
+    }
+
+    /// The total number of interrupt events that were lost because a subscriber's
+    /// channel was full.
+    pub fn interrupt_overflow_count(&self) -> u64 {
+        self.mcp23s17_state
+            .lock()
+            .interrupt_publisher
+            .overflow_count()
+    }
+
+    /// Connect the host inputs (_e.g._ Raspberry Pi GPIO pins) that are wired to the
+    /// MCP23S17's `INTA` and/or `INTB` outputs.
+    ///
+    /// Each of the `lines` is configured with the trigger edge and pull that suit the
+    /// interrupt outputs as currently set up by [`IOCON::INTPOL`] and [`IOCON::ODR`], so
+    /// the `IOCON` register should be configured first. Use
+    /// [`InterruptLines::wait_for_interrupts()`] to wait for, and then service, the
+    /// interrupts rather than polling the device, or
+    /// [`InterruptLines::service_until()`] to keep servicing them as they are raised.
+    ///
+    /// If only one of the outputs is wired, either [`IOCON::MIRROR`] must be set or only
+    /// the pins of the corresponding port can be waited for.
+    pub fn connect_interrupt_lines<L: InterruptLine>(
+        &self,
+        lines: Vec<L>,
+    ) -> Result<InterruptLines<L, T>> {
+        InterruptLines::new(lines, self.mcp23s17_state.clone())
     }
 
     /// Set the specified bits in the register.
@@ -551,7 +1209,7 @@ impl Mcp23s17 {
     /// it with `data` before writing it back to `register`. Note the race-hazard if
     /// there are multiple [`Mcp23s17`]s that can be writing to the same device.
     pub fn set_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
-        self.mcp23s17_state.borrow().set_bits(register, data)
+        self.mcp23s17_state.lock().set_bits(register, data)
     }
 
     /// Clear the specified bits in the register.
@@ -560,7 +1218,7 @@ impl Mcp23s17 {
     /// it with `!data` before writing it back to `register`. Note the race-hazard if
     /// there are multiple [`Mcp23s17`]s that can be writing to the same device.
     pub fn clear_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
-        self.mcp23s17_state.borrow().clear_bits(register, data)
+        self.mcp23s17_state.lock().clear_bits(register, data)
     }
 
     /// Set the specified bit in the register.
@@ -570,7 +1228,7 @@ impl Mcp23s17 {
     /// writing it back to `register`. Note the race-hazard if there are multiple
     /// [`Mcp23s17`]s that can be writing to the same device.
     pub fn set_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
-        self.mcp23s17_state.borrow().set_bit(register, bit)
+        self.mcp23s17_state.lock().set_bit(register, bit)
     }
 
     /// Clear the specified bit in the register.
@@ -580,7 +1238,7 @@ impl Mcp23s17 {
     /// writing it back to `register`. Note the race-hazard if there are multiple
     /// [`Mcp23s17`]s that can be writing to the same device.
     pub fn clear_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
-        self.mcp23s17_state.borrow().clear_bit(register, bit)
+        self.mcp23s17_state.lock().clear_bit(register, bit)
     }
 
     /// Get the specified bit in the register.
@@ -589,7 +1247,54 @@ impl Mcp23s17 {
     /// `register` and then ANDing with a mask with the appropriate bit set before
     /// converting to a [`Level`].
     pub fn get_bit(&self, register: RegisterAddress, bit: u8) -> Result<Level> {
-        self.mcp23s17_state.borrow().get_bit(register, bit)
+        self.mcp23s17_state.lock().get_bit(register, bit)
+    }
+
+    /// Enable the write-through shadow register cache, loading it from the device.
+    ///
+    /// With the cache enabled, reads of the configuration registers and the output
+    /// latches (`OLAT`) are served from the cache and the read-modify-write operations
+    /// (_e.g._ [`Mcp23s17::set_bit()`]) only need the single write transfer. Bit
+    /// operations on the `GPIO` registers are based on the cached `OLAT` value rather
+    /// than on the levels read from the pins.
+    ///
+    /// The cache assumes that nothing else writes to the device; if that is not the case
+    /// use [`Mcp23s17::resync()`] to reload it from the device.
+    pub fn enable_cache(&self) -> Result<()> {
+        self.mcp23s17_state.lock().enable_cache()
+    }
+
+    /// Disable the shadow register cache so that every access goes to the device.
+    pub fn disable_cache(&self) {
+        self.mcp23s17_state.lock().register_map.cache = None;
+    }
+
+    /// Reload the shadow register cache from the device.
+    ///
+    /// Does nothing if the cache is not enabled.
+    pub fn resync(&self) -> Result<()> {
+        self.mcp23s17_state.lock().resync()
+    }
+
+    /// Take an advisory lock on the lock file at `path` around each read-modify-write
+    /// of the device's registers, so that cooperating processes using the same lock
+    /// file don't lose each other's changes.
+    ///
+    /// The read-modify-writes then always read the register from the device, even if
+    /// the register cache is enabled. See [`ProcessLock::default_path()`] for the
+    /// conventional lock file.
+    #[cfg(feature = "process-lock")]
+    pub fn enable_process_lock(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let process_lock = ProcessLock::open(path)?;
+        self.mcp23s17_state.lock().register_map.process_lock =
+            Some(std::sync::Arc::new(process_lock));
+        Ok(())
+    }
+
+    /// Stop taking the advisory lock, closing the lock file.
+    #[cfg(feature = "process-lock")]
+    pub fn disable_process_lock(&self) {
+        self.mcp23s17_state.lock().register_map.process_lock = None;
     }
 
     /// Returns a [`Pin`] for the specified GPIO port and pin number.
@@ -600,93 +1305,462 @@ impl Mcp23s17 {
     ///
     /// After a [`Pin`] (or a derived [`InputPin`] or [`OutputPin`]) goes out of scope,
     /// it can be retrieved again through another `get()` call.
-    pub fn get(&self, port: Port, pin: u8) -> Result<Pin> {
+    pub fn get(&self, port: Port, pin: u8) -> Result<Pin<T>> {
         if pin > 7 {
             return Err(Mcp23s17Error::PinNotAvailable(pin));
         }
 
-        // Returns an error if the pin is already taken, otherwise sets it to true here
-        // Since we are guaranteed to be single-threaded this doesn't need to worry
-        // about synchronisation or races.
-        match port {
-            Port::GpioA => {
-                if self.mcp23s17_state.borrow().gpioa_pins_taken[pin as usize] {
-                    return Err(Mcp23s17Error::PinNotAvailable(pin));
-                }
-                {
-                    self.mcp23s17_state.borrow_mut().gpioa_pins_taken[pin as usize] = true;
-                }
-                Ok(Pin::new(port, pin, self.mcp23s17_state.clone()))
+        // Returns an error if the pin is already taken, otherwise sets it to true here.
+        // The check and the update are made under the one lock of the shared state (a
+        // `RefCell` borrow, or the `Mutex` with the `sync` feature) so no other thread
+        // can take the pin in between.
+        {
+            let mut mcp23s17_state = self.mcp23s17_state.lock();
+            let pins_taken = mcp23s17_state.pins_taken(port);
+            if pins_taken[pin as usize] {
+                return Err(Mcp23s17Error::PinNotAvailable(pin));
             }
-            Port::GpioB => {
-                if self.mcp23s17_state.borrow().gpiob_pins_taken[pin as usize] {
-                    return Err(Mcp23s17Error::PinNotAvailable(pin));
-                }
-                {
-                    self.mcp23s17_state.borrow_mut().gpiob_pins_taken[pin as usize] = true;
-                }
-                Ok(Pin::new(port, pin, self.mcp23s17_state.clone()))
+            pins_taken[pin as usize] = true;
+        }
+        Ok(Pin::new(port, pin, self.mcp23s17_state.clone()))
+    }
+
+    /// Returns a [`PortBus`] that owns all eight pins of the GPIO `port`.
+    ///
+    /// The pins are configured according to `direction` and `pullups`, which follow
+    /// the `IODIR` and `GPPU` registers: a `1` bit makes the pin an input, or connects
+    /// its pull-up, respectively.
+    ///
+    /// If any of the port's pins is already in use then `Mcp23s17::get_port_bus()`
+    /// returns `Err(`[`Mcp23s17Error::PinNotAvailable`]`)` for the first such pin.
+    /// Whilst the [`PortBus`] exists, none of the port's pins can be retrieved through
+    /// [`Mcp23s17::get()`].
+    pub fn get_port_bus(&self, port: Port, direction: u8, pullups: u8) -> Result<PortBus<T>> {
+        {
+            let mut mcp23s17_state = self.mcp23s17_state.lock();
+            let pins_taken = mcp23s17_state.pins_taken(port);
+            if let Some(pin) = pins_taken.iter().position(|&taken| taken) {
+                return Err(Mcp23s17Error::PinNotAvailable(pin as u8));
             }
+            pins_taken.fill(true);
         }
+        PortBus::new(port, direction, pullups, self.mcp23s17_state.clone())
     }
 
-    /// Get the SPI bus that the MCP23S17 is accessed over.
-    pub fn get_spi_bus(&self) -> SpiBus {
-        self.mcp23s17_state.borrow().spi_bus
+    /// Returns an [`AttachedPin`] for every pin, in the order `GPA0`..`GPA7` then
+    /// `GPB0`..`GPB7`, each reflecting how the device is already configured.
+    ///
+    /// The configuration registers of each port are read once and nothing is written
+    /// to the device. If any pin is already in use then `Mcp23s17::attach_pins()`
+    /// returns `Err(`[`Mcp23s17Error::PinNotAvailable`]`)` for the first such pin. See
+    /// [`Pin::into_attached_pin()`].
+    pub fn attach_pins(&self) -> Result<Vec<AttachedPin<T>>> {
+        let ports: &[Port] =
+            if self.mcp23s17_state.lock().register_map.layout == RegisterLayout::SinglePort {
+                &[Port::GpioA]
+            } else {
+                &[Port::GpioA, Port::GpioB]
+            };
+
+        // Take the pins and read all the configuration before attaching any pin, as
+        // dropping an attached pin on an error could write to the device.
+        let mut configured_ports = Vec::with_capacity(ports.len());
+        for &port in ports {
+            let pins = (0..8)
+                .map(|pin| self.get(port, pin))
+                .collect::<Result<Vec<_>>>()?;
+            let configuration = PortConfiguration::read(&mut self.mcp23s17_state.lock(), port)?;
+            configured_ports.push((pins, configuration));
+        }
+        Ok(configured_ports
+            .into_iter()
+            .flat_map(|(pins, configuration)| {
+                pins.into_iter().map(move |pin| pin.attach(&configuration))
+            })
+            .collect())
+    }
+
+    /// Get the SPI bus that the MCP23S17 is accessed over, if known.
+    ///
+    /// Returns `None` when the MCP23S17 was created with a user-supplied [`Transport`].
+    ///
+    /// Note that before the driver was generic over the [`Transport`] this returned a
+    /// plain [`SpiBus`]; an MCP23S17 created with [`Mcp23s17::new()`] always returns
+    /// `Some`.
+    pub fn get_spi_bus(&self) -> Option<SpiBus> {
+        self.mcp23s17_state.lock().spi_bus
     }
 
     /// Get the hardware address of the MCP23S17.
     pub fn get_hardware_address(&self) -> HardwareAddress {
-        self.mcp23s17_state.borrow().address
+        self.mcp23s17_state.lock().address
     }
+}
 
-    /// In testing environments provide an API to read the MockSpi registers.
-    #[cfg(any(feature = "mockspi", test))]
-    pub fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
-        self.mcp23s17_state.borrow().spi.get_mock_data(register)
+impl<T: Transport> Mcp23s17State<T> {
+    /// Create the state for a device accessed over `spi` with no pins yet taken.
+    fn new(spi: T, spi_bus: Option<SpiBus>, address: HardwareAddress) -> Self {
+        Mcp23s17State {
+            spi,
+            spi_bus,
+            address,
+            register_map: RegisterMap::default(),
+            gpioa_pins_taken: [false; 8],
+            gpiob_pins_taken: [false; 8],
+            interrupt_dispatcher: InterruptDispatcher::default(),
+            interrupt_publisher: InterruptPublisher::default(),
+        }
     }
 
-    /// In testing environments provide an API to write the MockSpi registers.
-    #[cfg(any(feature = "mockspi", test))]
-    pub fn set_mock_data(&self, register: RegisterAddress, data: u8) {
-        self.mcp23s17_state
-            .borrow_mut()
-            .spi
-            .set_mock_data(register, data);
+    /// Write the `config` to `IOCON` and verify that it reads back.
+    fn configure(&mut self, config: &Mcp23s17Config) -> Result<()> {
+        if config.probe() {
+            self.probe()?;
+        }
+        if config.reset() {
+            self.reset_to_por_defaults()?;
+        }
+        debug!("Configure IOCON={:?}", config.iocon());
+        self.write_iocon(config.iocon().bits())
     }
-}
 
-impl Mcp23s17State {
-    /// Read an MCP23S17 register.
-    fn read(&self, register: RegisterAddress) -> Result<u8> {
+    /// Write the power-on-reset values to the registers that exist in the device.
+    fn reset_to_por_defaults(&mut self) -> Result<()> {
+        /// The writable registers in the order to reset them, with their POR values.
+        const POR_VALUES: [(RegisterAddress, u8); 15] = [
+            (RegisterAddress::IOCON, 0x00),
+            (RegisterAddress::GPINTENA, 0x00),
+            (RegisterAddress::GPINTENB, 0x00),
+            (RegisterAddress::IODIRA, 0xff),
+            (RegisterAddress::IODIRB, 0xff),
+            (RegisterAddress::IPOLA, 0x00),
+            (RegisterAddress::IPOLB, 0x00),
+            (RegisterAddress::DEFVALA, 0x00),
+            (RegisterAddress::DEFVALB, 0x00),
+            (RegisterAddress::INTCONA, 0x00),
+            (RegisterAddress::INTCONB, 0x00),
+            (RegisterAddress::GPPUA, 0x00),
+            (RegisterAddress::GPPUB, 0x00),
+            (RegisterAddress::OLATA, 0x00),
+            (RegisterAddress::OLATB, 0x00),
+        ];
+
+        debug!("Reset device {} to POR defaults", self.address);
+        // Keep the device answering only to its own address.
+        let haen = IOCON::from_bits_retain(self.read_device(RegisterAddress::IOCON)?) & IOCON::HAEN;
+        for (register, value) in POR_VALUES {
+            if register == RegisterAddress::IOCON {
+                self.write_iocon(value | haen.bits())?;
+            } else if self.register_map.address(register).is_ok() {
+                self.write(register, value)?;
+            }
+        }
+        for register in [RegisterAddress::INTCAPA, RegisterAddress::INTCAPB] {
+            if self.register_map.address(register).is_ok() {
+                self.read_device(register)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that patterns written to `DEFVALA` read back, restoring its value.
+    fn probe(&mut self) -> Result<()> {
+        /// Patterns that a missing device can't return, since it reads as all 0s or 1s.
+        const PATTERNS: [u8; 2] = [0b1010_0101, 0b0101_1010];
+
+        debug!("Probe device {}", self.address);
+        #[cfg(feature = "process-lock")]
+        let process_lock = self.register_map.process_lock();
+        #[cfg(feature = "process-lock")]
+        let _guard = process_lock.as_deref().map(ProcessLock::lock).transpose()?;
+        let original = self.read_device(RegisterAddress::DEFVALA)?;
+        let mut responding = true;
+        for pattern in PATTERNS {
+            self.write(RegisterAddress::DEFVALA, pattern)?;
+            if self.read_device(RegisterAddress::DEFVALA)? != pattern {
+                responding = false;
+                break;
+            }
+        }
+        self.write(RegisterAddress::DEFVALA, original)?;
+        if !responding {
+            error!("Device {} not responding", self.address);
+            return Err(Mcp23s17Error::DeviceNotResponding);
+        }
+        Ok(())
+    }
+
+    /// Write `IOCON` and verify that it reads back from the device, at its new address
+    /// if the bank mode changed.
+    fn write_iocon(&mut self, written: u8) -> Result<()> {
+        let written = self
+            .register_map
+            .value_to_write(RegisterAddress::IOCON, written);
+        self.write(RegisterAddress::IOCON, written)?;
+        let read = self.read_device(RegisterAddress::IOCON)?;
+        if read != written {
+            error!("IOCON read back as 0x{read:02x} after writing 0x{written:02x}");
+            return Err(Mcp23s17Error::ConfigurationNotApplied { written, read });
+        }
+        Ok(())
+    }
+
+    /// The bank mode implied by the register layout.
+    fn bank_mode(&self) -> BankMode {
+        self.register_map.bank_mode()
+    }
+
+    /// Switch the device to `bank_mode`, leaving the other `IOCON` bits unchanged.
+    fn set_bank_mode(&mut self, bank_mode: BankMode) -> Result<()> {
+        self.register_map.check_bankable()?;
+        debug!("Set bank mode {bank_mode}");
+        let mut iocon = IOCON::from_bits_retain(self.read_device(RegisterAddress::IOCON)?);
+        iocon.set(IOCON::BANK, bank_mode == BankMode::Segregated);
+        self.write_iocon(iocon.bits())
+    }
+
+    /// Take the device to already be in `bank_mode`.
+    fn assume_bank_mode(&mut self, bank_mode: BankMode) -> Result<()> {
+        self.register_map.assume_bank_mode(bank_mode)?;
+        self.resync()
+    }
+
+    /// The record of which pins of `port` are in use.
+    fn pins_taken(&mut self, port: Port) -> &mut [bool; 8] {
+        match port {
+            Port::GpioA => &mut self.gpioa_pins_taken,
+            Port::GpioB => &mut self.gpiob_pins_taken,
+        }
+    }
+
+    /// Enable the shadow register cache, loading it from the device.
+    fn enable_cache(&mut self) -> Result<()> {
+        self.register_map.cache = Some([0u8; RegisterAddress::LENGTH]);
+        self.resync()
+    }
+
+    /// Reload the shadow register cache (if enabled) from the device.
+    fn resync(&mut self) -> Result<()> {
+        let registers = self.register_map.registers_to_cache();
+        if registers.is_empty() {
+            return Ok(());
+        }
+        debug!("Resync register cache");
+        let mut cache = [0u8; RegisterAddress::LENGTH];
+        for register in registers {
+            cache[register as usize] = self.read_device(register)?;
+        }
+        self.register_map.cache = Some(cache);
+        Ok(())
+    }
+
+    /// Read the current value of a register ready to modify some of its bits.
+    fn read_for_modify(&mut self, register: RegisterAddress) -> Result<u8> {
+        match self.register_map.cached_for_modify(register)? {
+            Some(value) => Ok(value),
+            None => self.read_device(register),
+        }
+    }
+
+    /// Read an MCP23S17 register, from the shadow register cache if possible.
+    fn read(&mut self, register: RegisterAddress) -> Result<u8> {
+        match self.register_map.cached(register)? {
+            Some(value) => Ok(value),
+            None => self.read_device(register),
+        }
+    }
+
+    /// Read an MCP23S17 register from the device.
+    fn read_device(&mut self, register: RegisterAddress) -> Result<u8> {
         debug!("Read {register:?}");
 
         let mut read_buffer = [0u8; 3];
         let mut write_buffer = [0u8; 3];
         write_buffer[0] = self.spi_control_byte(SpiCommand::Read);
-        write_buffer[1] = register as u8;
+        write_buffer[1] = self.register_map.address(register)?;
 
-        let read_length = self.spi.transfer(&mut read_buffer, &write_buffer)?;
-        if read_length != 3 {
-            error!("Unexpected number of bytes read ({read_length})");
-            return Err(Mcp23s17Error::UnexpectedReadLength(read_length));
-        }
+        self.transfer(&mut read_buffer, &write_buffer)?;
         debug!("Read value = 0x{:02x}", read_buffer[2]);
+        self.register_map.track_read(register, read_buffer[2]);
         Ok(read_buffer[2])
     }
 
     /// Write an MCP23S17 register.
-    fn write(&self, register: RegisterAddress, data: u8) -> Result<()> {
+    fn write(&mut self, register: RegisterAddress, data: u8) -> Result<()> {
+        let data = self.register_map.value_to_write(register, data);
         debug!("Write 0x{data:02x} to {register:?}");
 
         let mut read_buffer = [0u8; 3];
         let mut write_buffer = [0u8; 3];
         write_buffer[0] = self.spi_control_byte(SpiCommand::Write);
-        write_buffer[1] = register as u8;
+        write_buffer[1] = self.register_map.address(register)?;
         write_buffer[2] = data;
 
-        let read_length = self.spi.transfer(&mut read_buffer, &write_buffer)?;
-        if read_length != 3 {
+        self.transfer(&mut read_buffer, &write_buffer)?;
+        self.register_map.track_write(register, data);
+        Ok(())
+    }
+
+    /// Read consecutive registers from the device in a single transfer.
+    fn read_registers(&mut self, start: RegisterAddress, data: &mut [u8]) -> Result<()> {
+        debug!("Read {} registers from {start:?}", data.len());
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let mut write_buffer = vec![0u8; data.len() + 2];
+        write_buffer[0] = self.spi_control_byte(SpiCommand::Read);
+        write_buffer[1] = self.register_map.burst_address(start, data.len())?;
+        let mut read_buffer = vec![0u8; write_buffer.len()];
+
+        self.transfer(&mut read_buffer, &write_buffer)?;
+        data.copy_from_slice(&read_buffer[2..]);
+        debug!("Read values = {data:02x?}");
+        Ok(())
+    }
+
+    /// Write consecutive registers on the device in a single transfer.
+    fn write_registers(&mut self, start: RegisterAddress, data: &[u8]) -> Result<()> {
+        debug!("Write {data:02x?} to registers from {start:?}");
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let address = self.register_map.burst_address(start, data.len())?;
+        let data = self.register_map.burst_to_write(start, address, data)?;
+        let mut write_buffer = Vec::with_capacity(data.len() + 2);
+        write_buffer.push(self.spi_control_byte(SpiCommand::Write));
+        write_buffer.push(address);
+        write_buffer.extend_from_slice(&data);
+        let mut read_buffer = vec![0u8; write_buffer.len()];
+
+        self.transfer(&mut read_buffer, &write_buffer)?;
+        for (offset, &value) in (address..).zip(&data) {
+            let register = self.register_map.layout.register(offset)?;
+            self.register_map.track_write(register, value);
+        }
+        Ok(())
+    }
+
+    /// Read the interrupt flag and capture registers, in a single transfer if possible,
+    /// and decode them.
+    fn take_interrupts(&mut self) -> Result<Vec<InterruptEvent>> {
+        let (flags, captures) = if self.register_map.iocon.contains(IOCON::SEQOP) {
+            self.read_interrupts_in_byte_mode()?
+        } else {
+            self.read_interrupts_sequentially()?
+        };
+        let events = InterruptEvent::decode(flags, captures);
+        self.interrupt_publisher.publish(&events);
+        Ok(events)
+    }
+
+    /// Read the interrupt flag and capture registers without sequential operation,
+    /// returning the flags and captures of ports A and B.
+    ///
+    /// The address pointer doesn't increment, so the flags are read (as a pair where
+    /// possible) before the captures that clear them.
+    fn read_interrupts_in_byte_mode(&mut self) -> Result<([u8; 2], [u8; 2])> {
+        Ok(match self.register_map.layout {
+            RegisterLayout::Interleaved | RegisterLayout::Banked => (
+                self.read_pair(RegisterAddress::INTFA, RegisterAddress::INTFB)?
+                    .to_le_bytes(),
+                self.read_pair(RegisterAddress::INTCAPA, RegisterAddress::INTCAPB)?
+                    .to_le_bytes(),
+            ),
+            RegisterLayout::SinglePort => (
+                [self.read_device(RegisterAddress::INTFA)?, 0],
+                [self.read_device(RegisterAddress::INTCAPA)?, 0],
+            ),
+        })
+    }
+
+    /// Read the interrupt flag and capture registers using sequential operation,
+    /// returning the flags and captures of ports A and B.
+    fn read_interrupts_sequentially(&mut self) -> Result<([u8; 2], [u8; 2])> {
+        Ok(match self.register_map.layout {
+            RegisterLayout::Interleaved => {
+                // INTFA, INTFB, INTCAPA, INTCAPB
+                let mut data = [0u8; 4];
+                self.read_registers(RegisterAddress::INTFA, &mut data)?;
+                ([data[0], data[1]], [data[2], data[3]])
+            }
+            RegisterLayout::Banked => {
+                // INTFA, INTCAPA then INTFB, INTCAPB
+                let mut data_a = [0u8; 2];
+                let mut data_b = [0u8; 2];
+                self.read_registers(RegisterAddress::INTFA, &mut data_a)?;
+                self.read_registers(RegisterAddress::INTFB, &mut data_b)?;
+                ([data_a[0], data_b[0]], [data_a[1], data_b[1]])
+            }
+            RegisterLayout::SinglePort => {
+                // INTF, INTCAP
+                let mut data = [0u8; 2];
+                self.read_registers(RegisterAddress::INTFA, &mut data)?;
+                ([data[0], 0], [data[1], 0])
+            }
+        })
+    }
+
+    /// Read both `GPIO` registers, in a single transfer if possible.
+    fn read_gpio16(&mut self) -> Result<u16> {
+        self.read_pair(RegisterAddress::GPIOA, RegisterAddress::GPIOB)
+    }
+
+    /// Write both `OLAT` registers, in a single transfer if possible.
+    fn write_olat16(&mut self, value: u16) -> Result<()> {
+        self.write_pair(RegisterAddress::OLATA, RegisterAddress::OLATB, value)
+    }
+
+    /// Read the `A` and `B` registers of a pair from the device, in a single transfer
+    /// if possible.
+    fn read_pair(
+        &mut self,
+        register_a: RegisterAddress,
+        register_b: RegisterAddress,
+    ) -> Result<u16> {
+        if !self.register_map.pair_in_one_transfer() {
+            let data = [self.read_device(register_a)?, self.read_device(register_b)?];
+            return Ok(u16::from_le_bytes(data));
+        }
+        let mut data = [0u8; 2];
+        self.read_registers(register_a, &mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    /// Write the `A` and `B` registers of a pair, in a single transfer if possible.
+    fn write_pair(
+        &mut self,
+        register_a: RegisterAddress,
+        register_b: RegisterAddress,
+        value: u16,
+    ) -> Result<()> {
+        let [data_a, data_b] = value.to_le_bytes();
+        if !self.register_map.pair_in_one_transfer() {
+            self.write(register_a, data_a)?;
+            return self.write(register_b, data_b);
+        }
+        self.write_registers(register_a, &[data_a, data_b])
+    }
+
+    /// Modify the bits in `mask` of both `OLAT` registers.
+    fn modify_olat16(&mut self, mask: u16, value: u16) -> Result<()> {
+        debug!("Modify OLAT mask={mask:016b} value={value:016b}");
+        #[cfg(feature = "process-lock")]
+        let process_lock = self.register_map.process_lock();
+        #[cfg(feature = "process-lock")]
+        let _guard = process_lock.as_deref().map(ProcessLock::lock).transpose()?;
+        let olat = match self.register_map.cached_olat16_for_modify() {
+            Some(olat) => olat,
+            None => self.read_pair(RegisterAddress::OLATA, RegisterAddress::OLATB)?,
+        };
+        self.write_olat16((olat & !mask) | (value & mask))
+    }
+
+    /// Perform a transfer checking that the whole message was transferred.
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<()> {
+        let read_length = self.spi.transfer(read_buffer, write_buffer)?;
+        if read_length != write_buffer.len() {
             error!("Unexpected number of bytes read ({read_length})");
             return Err(Mcp23s17Error::UnexpectedReadLength(read_length));
         }
@@ -694,19 +1768,40 @@ impl Mcp23s17State {
     }
 
     /// Set the specified bits in the register.
-    fn set_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
+    fn set_bits(&mut self, register: RegisterAddress, data: u8) -> Result<()> {
         debug!("Set bits {data:08b} in {register:?}");
-        self.write(register, self.read(register)? | data)
+        self.read_modify_write(register, |value| value | data)
     }
 
     /// Clear the specified bits in the register.
-    fn clear_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
+    fn clear_bits(&mut self, register: RegisterAddress, data: u8) -> Result<()> {
         debug!("Clear bits {data:08b} in {register:?}");
-        self.write(register, self.read(register)? & !data)
+        self.read_modify_write(register, |value| value & !data)
+    }
+
+    /// Change the bits in `mask` to the corresponding bits of `data` in a single write.
+    fn modify_bits(&mut self, register: RegisterAddress, mask: u8, data: u8) -> Result<()> {
+        debug!("Modify bits {mask:08b} to {data:08b} in {register:?}");
+        self.read_modify_write(register, |value| (value & !mask) | (data & mask))
+    }
+
+    /// Write the register with the result of `modify` on its current value, holding
+    /// the process lock (if enabled) throughout.
+    fn read_modify_write(
+        &mut self,
+        register: RegisterAddress,
+        modify: impl FnOnce(u8) -> u8,
+    ) -> Result<()> {
+        #[cfg(feature = "process-lock")]
+        let process_lock = self.register_map.process_lock();
+        #[cfg(feature = "process-lock")]
+        let _guard = process_lock.as_deref().map(ProcessLock::lock).transpose()?;
+        let value = modify(self.read_for_modify(register)?);
+        self.write(register, value)
     }
 
     /// Set the specified bit (0-7) in the register.
-    fn set_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
+    fn set_bit(&mut self, register: RegisterAddress, bit: u8) -> Result<()> {
         debug!("Set bit {bit} in {register:?}");
         if bit > 7 {
             error!("Set bit {bit} is out of range (0-7)!");
@@ -716,7 +1811,7 @@ impl Mcp23s17State {
     }
 
     /// Clear the specified bit (0-7) in the register.
-    fn clear_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
+    fn clear_bit(&mut self, register: RegisterAddress, bit: u8) -> Result<()> {
         debug!("Clear bit {bit} in {register:?}");
         if bit > 7 {
             error!("Clear bit {bit} is out of range (0-7)!");
@@ -726,7 +1821,7 @@ impl Mcp23s17State {
     }
 
     /// Read the level of the specified bit (0-7).
-    fn get_bit(&self, register: RegisterAddress, bit: u8) -> Result<Level> {
+    fn get_bit(&mut self, register: RegisterAddress, bit: u8) -> Result<Level> {
         debug!("Get bit {bit} in {register:?}");
         if bit > 7 {
             error!("Get bit {bit} is out of range (0-7)!");
@@ -736,19 +1831,8 @@ impl Mcp23s17State {
     }
 
     /// Calculate the control byte to use in a message.
-    ///
-    /// The client address contains four fixed bits and three user-defined hardware
-    /// address bits (if enabled via `IOCON::HAEN`) (pins A2, A1 and A0) with the
-    /// read/write bit filling out the control byte.
     fn spi_control_byte(&self, command: SpiCommand) -> u8 {
-        let control_byte = 0x40 | self.address.0 << 1 | command as u8;
-        debug!(
-            "ControlByte: 0x{:02x} (Command='{:?}' address={:?})",
-            control_byte,
-            command,
-            u8::from(self.address)
-        );
-        control_byte
+        self.address.control_byte(command)
     }
 }
 
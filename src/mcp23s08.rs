@@ -0,0 +1,374 @@
+//! Support for the 8-bit MCP23S08 (SPI) and MCP23008 (I2C) siblings of the MCP23S17.
+//!
+//! These devices have a single GPIO port whose registers are packed together at
+//! addresses IODIR=0x00 through to OLAT=0x0A. The registers are named using the port A
+//! [`RegisterAddress`]es (_e.g._ [`RegisterAddress::GPIOA`]) and any attempt to access
+//! a port B register fails with [`Mcp23s17Error::RegisterAddressBoundsError`].
+//!
+//! Since there is only one port, [`Mcp23s08::get()`] just takes the pin number and
+//! returns the same [`Pin`] type as the MCP23S17 so that the [`InputPin`] and
+//! [`OutputPin`] APIs are shared by both families.
+//!
+//! [`InputPin`]: crate::InputPin
+//! [`OutputPin`]: crate::OutputPin
+
+use std::sync::mpsc::Receiver;
+
+use rppal::{i2c::I2c, spi::Spi};
+
+#[cfg(any(test, feature = "mockspi"))]
+use super::mock_spi::MockSpi;
+use super::{
+    AttachedPin, ChipSelect, HardwareAddress, I2cBus, I2cTransport, IOCON, InterruptEvent,
+    InterruptLine, InterruptLines, Level, Mcp23s17, Mcp23s17Config, Mcp23s17Error, Mcp23s17State,
+    Pin, Port, PortBus, RegisterAddress, RegisterLayout, Result, SpiBus, SpiMode,
+    TimestampedInterruptEvent, Transport,
+};
+
+/// A structure that represents an instance of the MCP23S08 I/O expander chip.
+///
+/// ```no_run
+/// use rppal_mcp23s17::{ChipSelect, HardwareAddress, Mcp23s08, SpiBus, SpiMode};
+///
+/// // Create an instance of the driver for the device with the hardware address
+/// // (A1, A0) of 0b01.
+/// let mcp23s08 = Mcp23s08::new(
+///     HardwareAddress::new(1).expect("Invalid hardware address"),
+///     SpiBus::Spi0,
+///     ChipSelect::Cs0,
+///     100_000,
+///     SpiMode::Mode0,
+/// )
+/// .expect("Failed to create MCP23S08");
+///
+/// let pin = mcp23s08
+///     .get(3)
+///     .expect("Failed to get Pin")
+///     .into_pullup_input_pin()
+///     .expect("Failed to convert to InputPin");
+/// ```
+#[derive(Debug)]
+pub struct Mcp23s08<T: Transport = Spi> {
+    mcp23s17: Mcp23s17<T>,
+}
+
+/// The MCP23008 is the I2C twin of the MCP23S08.
+///
+/// Unlike the MCP23S08, the MCP23008 has all three hardware address pins.
+pub type Mcp23008<I2C = I2c> = Mcp23s08<I2cTransport<I2C>>;
+
+impl Mcp23s08<Spi> {
+    /// Create an MCP23S08 instance accessed over the Raspberry Pi's SPI.
+    pub fn new(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Self> {
+        Self::check_address(address)?;
+        let spi = Spi::new(spi_bus, chip_select.into(), spi_clock, spi_mode)?;
+        Ok(Mcp23s08::from_state(Mcp23s17State::new(
+            spi,
+            Some(spi_bus),
+            address,
+        )))
+    }
+
+    /// Create an MCP23S08 instance accessed over the Raspberry Pi's SPI and attach to all
+    /// of its pins as they are already configured, without writing to the device.
+    ///
+    /// See [`Mcp23s17::attach()`].
+    pub fn attach(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<(Self, Vec<AttachedPin>)> {
+        let mcp23s08 = Mcp23s08::new(address, spi_bus, chip_select, spi_clock, spi_mode)?;
+        let pins = mcp23s08.attach_pins()?;
+        Ok((mcp23s08, pins))
+    }
+}
+
+impl Mcp23008<I2c> {
+    /// Create an MCP23008 instance accessed over the Raspberry Pi's I2C bus `i2c_bus`.
+    pub fn new_i2c(address: HardwareAddress, i2c_bus: u8) -> Result<Self> {
+        Ok(Mcp23008::with_i2c(address, I2c::with_bus(i2c_bus)?))
+    }
+}
+
+impl<I2C: I2cBus> Mcp23008<I2C> {
+    /// Create an MCP23008 instance accessed over a user-supplied [`I2cBus`].
+    pub fn with_i2c(address: HardwareAddress, i2c: I2C) -> Self {
+        Mcp23s08::from_state(Mcp23s17State::new(I2cTransport::new(i2c), None, address))
+    }
+}
+
+#[cfg(any(test, feature = "mockspi"))]
+impl Mcp23s08<MockSpi> {
+    /// Create an MCP23S08 instance with mock hardware.
+    pub fn new_mock(
+        address: HardwareAddress,
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        spi_clock: u32,
+        spi_mode: SpiMode,
+    ) -> Result<Self> {
+        Self::check_address(address)?;
+        let spi = MockSpi::new_single_port(spi_bus, chip_select, spi_clock, spi_mode);
+        Ok(Mcp23s08::from_state(Mcp23s17State::new(
+            spi,
+            Some(spi_bus),
+            address,
+        )))
+    }
+
+    /// In testing environments provide an API to read the MockSpi registers.
+    pub fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
+        self.mcp23s17.get_mock_data(register)
+    }
+
+    /// In testing environments provide an API to write the MockSpi registers.
+    pub fn set_mock_data(&self, register: RegisterAddress, data: u8) {
+        self.mcp23s17.set_mock_data(register, data);
+    }
+}
+
+impl<T: Transport> Mcp23s08<T> {
+    /// The MCP23S08 only has two hardware address pins (`A1` and `A0`) so 0-3 are
+    /// valid.
+    pub const MAX_HARDWARE_ADDRESS: u8 = 3;
+
+    /// Create an MCP23S08 instance accessed over a user-supplied [`Transport`].
+    ///
+    /// Returns [`Mcp23s17Error::HardwareAddressBoundsError`] if the `address` is beyond
+    /// [`Mcp23s08::MAX_HARDWARE_ADDRESS`].
+    pub fn with_transport(address: HardwareAddress, transport: T) -> Result<Self> {
+        Self::check_address(address)?;
+        Ok(Mcp23s08::from_state(Mcp23s17State::new(
+            transport, None, address,
+        )))
+    }
+
+    /// Check that the `address` is within the range of the MCP23S08 address pins.
+    fn check_address(address: HardwareAddress) -> Result<()> {
+        if u8::from(address) <= Self::MAX_HARDWARE_ADDRESS {
+            Ok(())
+        } else {
+            Err(Mcp23s17Error::HardwareAddressBoundsError(address.into()))
+        }
+    }
+
+    /// Wrap the state shared with the [`Pin`]s, using the single-port register layout.
+    fn from_state(mut mcp23s17_state: Mcp23s17State<T>) -> Self {
+        mcp23s17_state.register_map.layout = RegisterLayout::SinglePort;
+        Mcp23s08 {
+            mcp23s17: Mcp23s17::from_state(mcp23s17_state),
+        }
+    }
+
+    /// Apply the `config` to the device.
+    ///
+    /// The MCP23S08 only has one `INT` output and one bank of registers so
+    /// [`Mcp23s17ConfigBuilder::mirror()`] and [`Mcp23s17ConfigBuilder::bank()`] are
+    /// not supported. See [`Mcp23s17::configure()`].
+    ///
+    /// [`Mcp23s17ConfigBuilder::mirror()`]: crate::Mcp23s17ConfigBuilder::mirror
+    /// [`Mcp23s17ConfigBuilder::bank()`]: crate::Mcp23s17ConfigBuilder::bank
+    pub fn configure(&self, config: &Mcp23s17Config) -> Result<()> {
+        if config.iocon().contains(IOCON::MIRROR) {
+            return Err(Mcp23s17Error::UnsupportedConfiguration(
+                "MIRROR on a single-port device",
+            ));
+        }
+        if config.iocon().contains(IOCON::BANK) {
+            return Err(Mcp23s17Error::UnsupportedConfiguration(
+                "BANK on a single-port device",
+            ));
+        }
+        self.mcp23s17.configure(config)
+    }
+
+    /// Write the power-on-reset values to all the registers.
+    ///
+    /// See [`Mcp23s17::reset_to_por_defaults()`].
+    pub fn reset_to_por_defaults(&self) -> Result<()> {
+        self.mcp23s17.reset_to_por_defaults()
+    }
+
+    /// Check that the device is present and responding.
+    ///
+    /// See [`Mcp23s17::probe()`].
+    pub fn probe(&self) -> Result<()> {
+        self.mcp23s17.probe()
+    }
+
+    /// Read a byte from the MCP23S08 register at the address `register`.
+    pub fn read(&self, register: RegisterAddress) -> Result<u8> {
+        self.mcp23s17.read(register)
+    }
+
+    /// Write the byte `data` to the MCP23S08 register at address `register`.
+    pub fn write(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        self.mcp23s17.write(register, data)
+    }
+
+    /// Read consecutive registers, starting at `start`, into `data` in a single
+    /// transfer.
+    ///
+    /// See [`Mcp23s17::read_registers()`].
+    pub fn read_registers(&self, start: RegisterAddress, data: &mut [u8]) -> Result<()> {
+        self.mcp23s17.read_registers(start, data)
+    }
+
+    /// Write the bytes in `data` to consecutive registers, starting at `start`, in a
+    /// single transfer.
+    ///
+    /// See [`Mcp23s17::write_registers()`].
+    pub fn write_registers(&self, start: RegisterAddress, data: &[u8]) -> Result<()> {
+        self.mcp23s17.write_registers(start, data)
+    }
+
+    /// Read and decode the pending interrupts.
+    ///
+    /// See [`Mcp23s17::take_interrupts()`].
+    pub fn take_interrupts(&self) -> Result<Vec<InterruptEvent>> {
+        self.mcp23s17.take_interrupts()
+    }
+
+    /// Service the pending interrupts, calling the callback registered for each pin
+    /// that raised an interrupt with the captured level.
+    ///
+    /// See [`Mcp23s17::service_interrupts()`].
+    pub fn service_interrupts(&self) -> Result<Vec<InterruptEvent>> {
+        self.mcp23s17.service_interrupts()
+    }
+
+    /// Connect the host input (_e.g._ a Raspberry Pi GPIO pin) that is wired to the
+    /// MCP23S08's `INT` output.
+    ///
+    /// See [`Mcp23s17::connect_interrupt_lines()`].
+    pub fn connect_interrupt_lines<L: InterruptLine>(
+        &self,
+        lines: Vec<L>,
+    ) -> Result<InterruptLines<L, T>> {
+        self.mcp23s17.connect_interrupt_lines(lines)
+    }
+
+    /// Subscribe to the interrupt events.
+    ///
+    /// See [`Mcp23s17::subscribe()`].
+    pub fn subscribe(&self, capacity: usize) -> Receiver<TimestampedInterruptEvent> {
+        self.mcp23s17.subscribe(capacity)
+    }
+
+    /// The total number of interrupt events that were lost because a subscriber's
+    /// channel was full.
+    pub fn interrupt_overflow_count(&self) -> u64 {
+        self.mcp23s17.interrupt_overflow_count()
+    }
+
+    /// Set the specified bits in the register.
+    ///
+    /// See [`Mcp23s17::set_bits()`].
+    pub fn set_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        self.mcp23s17.set_bits(register, data)
+    }
+
+    /// Clear the specified bits in the register.
+    ///
+    /// See [`Mcp23s17::clear_bits()`].
+    pub fn clear_bits(&self, register: RegisterAddress, data: u8) -> Result<()> {
+        self.mcp23s17.clear_bits(register, data)
+    }
+
+    /// Set the specified bit in the register.
+    ///
+    /// See [`Mcp23s17::set_bit()`].
+    pub fn set_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
+        self.mcp23s17.set_bit(register, bit)
+    }
+
+    /// Clear the specified bit in the register.
+    ///
+    /// See [`Mcp23s17::clear_bit()`].
+    pub fn clear_bit(&self, register: RegisterAddress, bit: u8) -> Result<()> {
+        self.mcp23s17.clear_bit(register, bit)
+    }
+
+    /// Get the specified bit in the register.
+    ///
+    /// See [`Mcp23s17::get_bit()`].
+    pub fn get_bit(&self, register: RegisterAddress, bit: u8) -> Result<Level> {
+        self.mcp23s17.get_bit(register, bit)
+    }
+
+    /// Enable the write-through shadow register cache, loading it from the device.
+    ///
+    /// See [`Mcp23s17::enable_cache()`].
+    pub fn enable_cache(&self) -> Result<()> {
+        self.mcp23s17.enable_cache()
+    }
+
+    /// Disable the shadow register cache so that every access goes to the device.
+    pub fn disable_cache(&self) {
+        self.mcp23s17.disable_cache();
+    }
+
+    /// Reload the shadow register cache from the device.
+    ///
+    /// Does nothing if the cache is not enabled.
+    pub fn resync(&self) -> Result<()> {
+        self.mcp23s17.resync()
+    }
+
+    /// Take an advisory lock on the lock file at `path` around each read-modify-write
+    /// of the device's registers.
+    ///
+    /// See [`Mcp23s17::enable_process_lock()`].
+    #[cfg(feature = "process-lock")]
+    pub fn enable_process_lock(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        self.mcp23s17.enable_process_lock(path)
+    }
+
+    /// Stop taking the advisory lock, closing the lock file.
+    #[cfg(feature = "process-lock")]
+    pub fn disable_process_lock(&self) {
+        self.mcp23s17.disable_process_lock();
+    }
+
+    /// Returns a [`Pin`] for the specified pin number on the device's only GPIO port.
+    ///
+    /// If the pin is already in use, or the pin number `pin` is greater than 7 then
+    /// `Mcp23s08::get()` returns `Err(`[`Mcp23s17Error::PinNotAvailable`]`)`.
+    pub fn get(&self, pin: u8) -> Result<Pin<T>> {
+        self.mcp23s17.get(Port::GpioA, pin)
+    }
+
+    /// Returns an [`AttachedPin`] for each of the eight pins, in order, reflecting how
+    /// the device is already configured.
+    ///
+    /// See [`Mcp23s17::attach_pins()`].
+    pub fn attach_pins(&self) -> Result<Vec<AttachedPin<T>>> {
+        self.mcp23s17.attach_pins()
+    }
+
+    /// Returns a [`PortBus`] that owns all eight pins of the device's GPIO port.
+    ///
+    /// See [`Mcp23s17::get_port_bus()`].
+    pub fn get_port_bus(&self, direction: u8, pullups: u8) -> Result<PortBus<T>> {
+        self.mcp23s17.get_port_bus(Port::GpioA, direction, pullups)
+    }
+
+    /// Get the SPI bus that the MCP23S08 is accessed over, if known.
+    pub fn get_spi_bus(&self) -> Option<SpiBus> {
+        self.mcp23s17.get_spi_bus()
+    }
+
+    /// Get the hardware address of the MCP23S08.
+    pub fn get_hardware_address(&self) -> HardwareAddress {
+        self.mcp23s17.get_hardware_address()
+    }
+}
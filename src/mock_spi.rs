@@ -2,9 +2,24 @@
 //! MCP23S17 registers to be set and read by the test harness and then accessed over the
 //! "SPI" `transfer()` API.
 //!
-use std::cell::RefCell;
+//! The same registers can also be accessed through [`MockI2c`] which behaves as an
+//! MCP23017 on an I2C bus.
+//!
+//! Several devices sharing a chip select are mocked by a [`MockSpiBus`].
+//!
+//! A [`MockInterruptLine`] stands in for the host GPIO wired to the `INT` outputs.
+//!
+use std::{
+    cell::{Cell, RefCell},
+    io,
+    rc::Rc,
+    time::Duration,
+};
 
-use crate::{ChipSelect, RegisterAddress};
+use crate::{
+    ChipSelect, HardwareAddress, I2cBus, IOCON, InterruptLine, Mcp23s17Error, RegisterAddress,
+    RegisterLayout, Result, Transport,
+};
 
 /// A mock for the SPI hardware to use during testing.
 ///
@@ -17,54 +32,143 @@ pub struct MockSpi {
     read_access_count: RefCell<[usize; RegisterAddress::LENGTH]>,
     write_access_count: RefCell<[usize; RegisterAddress::LENGTH]>,
     hardware_present: bool,
+    /// Register layout of the mocked device, used to locate the registers addressed in
+    /// the transfers. The registers themselves are held in [`RegisterAddress`] order
+    /// so that the layout can follow the `BANK` bit of `IOCON`.
+    layout: Cell<RegisterLayout>,
+}
+
+impl Transport for MockSpi {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        MockSpi::transfer(self, read_buffer, write_buffer)
+    }
 }
 
 impl MockSpi {
     /// Crude emulation of the SPI transfer method specific to MCP23S17 use.
     ///
-    /// Assumes normally going to be reading single bytes of register data so that the
-    /// read and write buffers will both be of length 3.
+    /// Assumes that the first byte of the write buffer is the control byte, the second
+    /// is the register address and that the remaining bytes are register data. Where
+    /// there is more than one byte of data, the register address is advanced after each
+    /// byte as the real device does: incrementing (and wrapping back to the first
+    /// register) if sequential operation is enabled in `IOCON`, or otherwise toggling
+    /// between the `A` and `B` registers of the pair.
     ///
-    /// Assumes that the second byte of the write buffer is the register address.
+    /// Writing `IOCON` switches between the interleaved and banked layouts according to
+    /// its `BANK` bit. Addresses that do not hold a register in the current layout read
+    /// as zero and ignore writes.
     ///
     /// ## Special Case
     ///
     /// Any device created on Bus::Spi6 is simulated to "not exist": reads and writes
     /// both succeed, but reads always return zero. (The mock registers still get updated
     /// and the access counts are maintained as normal.)
-    pub(crate) fn transfer(
-        &self,
-        read_buffer: &mut [u8],
-        write_buffer: &[u8],
-    ) -> rppal::spi::Result<usize> {
-        assert_eq!(read_buffer.len(), 3);
-        assert_eq!(write_buffer.len(), 3);
+    pub(crate) fn transfer(&self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        assert!(write_buffer.len() >= 3);
+        assert_eq!(read_buffer.len(), write_buffer.len());
 
         println!("MockSpi::transfer write={write_buffer:?}");
-        let register = write_buffer[1] as usize;
+        let mut address = write_buffer[1];
+        for index in 2..write_buffer.len() {
+            let register = self.layout.get().register(address).ok();
+            if (write_buffer[0] & 0b0000_0001) != 0 {
+                // Reading from register.
+                read_buffer[index] = 0;
+                if let Some(register) = register {
+                    self.read_access_count.borrow_mut()[Self::index(register)] += 1;
+                    if self.hardware_present {
+                        read_buffer[index] = self.register_values.borrow()[Self::index(register)]
+                            ^ self.polarity(register);
+                    }
+                    self.clear_interrupt(register);
+                }
+            } else if let Some(register) = register {
+                // Writing to register.
+                self.write_access_count.borrow_mut()[Self::index(register)] += 1;
+                self.store(register, write_buffer[index]);
+            }
+            address = self.next_address(address);
+        }
         if (write_buffer[0] & 0b0000_0001) != 0 {
-            // Reading from register.
-            self.read_access_count.borrow_mut()[register] += 1;
             if self.hardware_present {
-                read_buffer[read_buffer.len() - 1] = self.register_values.borrow()[register];
                 println!("MockSpi::transfer (hardware present) read={read_buffer:?}");
             } else {
-                read_buffer[read_buffer.len() - 1] = 0;
                 println!("MockSpi::transfer (NO HARDWARE!) read={read_buffer:?}");
             }
-        } else {
-            // Writing to register.
-            self.write_access_count.borrow_mut()[register] += 1;
-            self.register_values.borrow_mut()[register] = write_buffer[2];
         }
 
         Ok(read_buffer.len())
     }
 
+    /// Emulate the input polarity inversion: reads of the `GPIO` and `INTCAP` registers
+    /// report the inverse of the (mock) pin level for the inputs with their `IPOL` bit
+    /// set. Returns the bits to invert when reading `register`.
+    fn polarity(&self, register: RegisterAddress) -> u8 {
+        let (ipol, iodir) = match register {
+            RegisterAddress::GPIOA | RegisterAddress::INTCAPA => {
+                (RegisterAddress::IPOLA, RegisterAddress::IODIRA)
+            }
+            RegisterAddress::GPIOB | RegisterAddress::INTCAPB => {
+                (RegisterAddress::IPOLB, RegisterAddress::IODIRB)
+            }
+            _ => return 0,
+        };
+        let registers = self.register_values.borrow();
+        registers[Self::index(ipol)] & registers[Self::index(iodir)]
+    }
+
+    /// Emulate the device clearing the interrupt condition of a port when its `GPIO` or
+    /// `INTCAP` register is read.
+    fn clear_interrupt(&self, register: RegisterAddress) {
+        let intf = match register {
+            RegisterAddress::GPIOA | RegisterAddress::INTCAPA => RegisterAddress::INTFA,
+            RegisterAddress::GPIOB | RegisterAddress::INTCAPB => RegisterAddress::INTFB,
+            _ => return,
+        };
+        self.register_values.borrow_mut()[Self::index(intf)] = 0;
+    }
+
+    /// The address that the device's address pointer moves on to after accessing
+    /// `address`.
+    fn next_address(&self, address: u8) -> u8 {
+        let layout = self.layout.get();
+        let iocon = self.register_values.borrow()[Self::index(RegisterAddress::IOCON)];
+        if !IOCON::from_bits_retain(iocon).contains(IOCON::SEQOP) {
+            // Skip over any gap in the layout's addresses.
+            let mut next = address;
+            loop {
+                next = ((usize::from(next) + 1) % layout.length()) as u8;
+                if layout.register(next).is_ok() {
+                    return next;
+                }
+            }
+        } else if layout == RegisterLayout::Interleaved {
+            address ^ 0x01
+        } else {
+            address
+        }
+    }
+
+    /// Store `data` in the `register`, following any change to the `BANK` bit of
+    /// `IOCON`.
+    fn store(&self, register: RegisterAddress, data: u8) {
+        self.register_values.borrow_mut()[Self::index(register)] = data;
+        if Self::index(register) == Self::index(RegisterAddress::IOCON)
+            && self.layout.get() != RegisterLayout::SinglePort
+        {
+            self.layout
+                .set(if IOCON::from_bits_retain(data).contains(IOCON::BANK) {
+                    RegisterLayout::Banked
+                } else {
+                    RegisterLayout::Interleaved
+                });
+        }
+    }
+
     /// Store of mock data to a register
     pub(crate) fn set_mock_data(&self, register: RegisterAddress, data: u8) {
         println!("Store mock data (0x{data:02x}) to read from {register:?}");
-        self.register_values.borrow_mut()[register as usize] = data;
+        self.store(register, data);
     }
 
     /// Get mock data from a register
@@ -75,9 +179,10 @@ impl MockSpi {
     /// - How many times the register has been read
     /// - How many times the register has been written
     pub(crate) fn get_mock_data(&self, register: RegisterAddress) -> (u8, usize, usize) {
-        let data = self.register_values.borrow()[register as usize];
-        let reads = self.read_access_count.borrow()[register as usize];
-        let writes = self.write_access_count.borrow()[register as usize];
+        let index = Self::index(register);
+        let data = self.register_values.borrow()[index];
+        let reads = self.read_access_count.borrow()[index];
+        let writes = self.write_access_count.borrow()[index];
         println!(
             "Retrieve mock data (0x{data:02x}) written to {register:?} (r={reads} w={writes})"
         );
@@ -108,4 +213,240 @@ impl MockSpi {
         mock_spi.hardware_present = !(bus == rppal::spi::Bus::Spi6);
         mock_spi
     }
+
+    /// Create a MockSpi for a single-port device (_e.g._ MCP23S08) setting registers
+    /// to match real hardware after power-on-reset.
+    pub(crate) fn new_single_port(
+        bus: rppal::spi::Bus,
+        chip_select: ChipSelect,
+        frequency: u32,
+        mode: rppal::spi::Mode,
+    ) -> MockSpi {
+        let mock_spi = MockSpi::new(bus, chip_select, frequency, mode);
+        mock_spi.layout.set(RegisterLayout::SinglePort);
+        mock_spi.register_values.borrow_mut()[RegisterAddress::IODIRB as usize] = 0;
+        mock_spi
+    }
+
+    /// Index of the `register` in the mock register arrays. Both `IOCON` addresses
+    /// access the same register.
+    fn index(register: RegisterAddress) -> usize {
+        match register {
+            RegisterAddress::IOCON2 => RegisterAddress::IOCON as usize,
+            _ => register as usize,
+        }
+    }
+}
+
+/// Allow the mock to stand in for an [`embedded_hal::spi::SpiDevice`] so that the
+/// [`SpiDeviceTransport`][crate::SpiDeviceTransport] can be tested.
+///
+/// Only full-duplex transfers are supported since they are all the driver uses.
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::spi::ErrorType for MockSpi {
+    type Error = embedded_hal::spi::ErrorKind;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::spi::SpiDevice for MockSpi {
+    fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal::spi::Operation<'_, u8>],
+    ) -> std::result::Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                embedded_hal::spi::Operation::Transfer(read_buffer, write_buffer) => {
+                    Transport::transfer(self, read_buffer, write_buffer)
+                        .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
+                }
+                _ => return Err(embedded_hal::spi::ErrorKind::Other),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal_async::spi::SpiDevice for MockSpi {
+    async fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal::spi::Operation<'_, u8>],
+    ) -> std::result::Result<(), Self::Error> {
+        embedded_hal::spi::SpiDevice::transaction(self, operations)
+    }
+}
+
+/// A mock for several MCP23S17s sharing one SPI chip select, each held in a [`MockSpi`].
+///
+/// As with the real device, a device whose hardware address pins are disabled
+/// ([`IOCON::HAEN`] clear) answers to every address in the control byte. Where more
+/// than one device answers a read, their outputs are ORed together.
+#[derive(Debug)]
+pub struct MockSpiBus {
+    devices: Vec<(HardwareAddress, MockSpi)>,
+}
+
+impl MockSpiBus {
+    /// Create a MockSpiBus with a device at each of the `addresses`, with registers
+    /// matching real hardware after power-on-reset.
+    pub(crate) fn new(
+        bus: rppal::spi::Bus,
+        chip_select: ChipSelect,
+        frequency: u32,
+        mode: rppal::spi::Mode,
+        addresses: &[HardwareAddress],
+    ) -> MockSpiBus {
+        MockSpiBus {
+            devices: addresses
+                .iter()
+                .map(|&address| (address, MockSpi::new(bus, chip_select, frequency, mode)))
+                .collect(),
+        }
+    }
+
+    /// The mock device at `address`.
+    pub(crate) fn device(&self, address: HardwareAddress) -> &MockSpi {
+        self.devices
+            .iter()
+            .find(|(device_address, _)| *device_address == address)
+            .map(|(_, device)| device)
+            .expect("No mock device at the address")
+    }
+}
+
+impl Transport for MockSpiBus {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        let address = (write_buffer[0] >> 1) & HardwareAddress::MAX_HARDWARE_ADDRESS;
+        read_buffer.fill(0);
+        let mut device_read_buffer = vec![0u8; read_buffer.len()];
+        for (device_address, device) in &self.devices {
+            let iocon = device.get_mock_data(RegisterAddress::IOCON).0;
+            if IOCON::from_bits_retain(iocon).contains(IOCON::HAEN)
+                && u8::from(*device_address) != address
+            {
+                continue;
+            }
+            println!("MockSpiBus: device {device_address} answers address {address}");
+            device.transfer(&mut device_read_buffer, write_buffer)?;
+            for (read, device_read) in read_buffer.iter_mut().zip(&device_read_buffer) {
+                *read |= device_read;
+            }
+        }
+        Ok(read_buffer.len())
+    }
+}
+
+/// A mock for an I2C bus with a single MCP23017 on it, whose registers are held in a
+/// [`MockSpi`].
+#[derive(Debug)]
+pub struct MockI2c {
+    pub(crate) registers: MockSpi,
+    address: HardwareAddress,
+    slave_address: Option<u16>,
+}
+
+impl MockI2c {
+    /// Create a MockI2c with an MCP23017 at `address` with registers matching real
+    /// hardware after power-on-reset.
+    pub(crate) fn new(address: HardwareAddress) -> MockI2c {
+        println!("Mock I2C created:\n  address: {address}");
+        MockI2c {
+            registers: MockSpi::new(
+                rppal::spi::Bus::Spi0,
+                ChipSelect::Cs0,
+                0,
+                rppal::spi::Mode::Mode0,
+            ),
+            address,
+            slave_address: None,
+        }
+    }
+
+    /// Emulate the device not acknowledging if it is not the one addressed.
+    fn check_acknowledge(&self) -> Result<()> {
+        if self.slave_address == Some(0x20 | u16::from(u8::from(self.address))) {
+            Ok(())
+        } else {
+            println!("MockI2c: no ACK from {:?}", self.slave_address);
+            Err(Mcp23s17Error::I2cError {
+                source: rppal::i2c::Error::Io(io::Error::other("No ACK")),
+            })
+        }
+    }
+}
+
+impl I2cBus for MockI2c {
+    fn set_slave_address(&mut self, slave_address: u16) -> Result<()> {
+        println!("MockI2c::set_slave_address 0x{slave_address:02x}");
+        self.slave_address = Some(slave_address);
+        Ok(())
+    }
+
+    /// Writes of a register address followed by the register data.
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        self.check_acknowledge()?;
+        let mut write_buffer = vec![0x40 | u8::from(self.address) << 1];
+        write_buffer.extend_from_slice(buffer);
+        self.registers
+            .transfer(&mut vec![0u8; write_buffer.len()], &write_buffer)?;
+        Ok(())
+    }
+
+    /// Write of a register address followed by a read of the register data.
+    fn write_read(&mut self, write_buffer: &[u8], read_buffer: &mut [u8]) -> Result<()> {
+        self.check_acknowledge()?;
+        let mut spi_write_buffer = vec![0u8; read_buffer.len() + 2];
+        spi_write_buffer[0] = 0x40 | u8::from(self.address) << 1 | 0x01;
+        spi_write_buffer[1] = write_buffer[0];
+        let mut spi_read_buffer = vec![0u8; spi_write_buffer.len()];
+        self.registers
+            .transfer(&mut spi_read_buffer, &spi_write_buffer)?;
+        read_buffer.copy_from_slice(&spi_read_buffer[2..]);
+        Ok(())
+    }
+}
+
+/// A mock for the host GPIO wired to one of the MCP23S17's `INT` outputs.
+///
+/// Clones share the same state so that the test harness can keep a clone with which to
+/// assert the interrupt output after the original has been connected to the device.
+#[derive(Clone, Debug, Default)]
+pub struct MockInterruptLine {
+    active: Rc<Cell<bool>>,
+    configured_active_high: Rc<Cell<Option<bool>>>,
+}
+
+impl MockInterruptLine {
+    /// Create a MockInterruptLine whose interrupt output is inactive.
+    pub fn new() -> MockInterruptLine {
+        MockInterruptLine::default()
+    }
+
+    /// Set whether the interrupt output is active.
+    pub fn set_active(&self, active: bool) {
+        self.active.set(active);
+    }
+
+    /// How the line was configured: `Some(true)` for an active-high interrupt output.
+    pub fn configured_active_high(&self) -> Option<bool> {
+        self.configured_active_high.get()
+    }
+}
+
+impl InterruptLine for MockInterruptLine {
+    fn configure(&mut self, active_high: bool) -> Result<()> {
+        println!("MockInterruptLine::configure active_high={active_high}");
+        self.configured_active_high.set(Some(active_high));
+        Ok(())
+    }
+
+    fn is_high(&mut self) -> Result<bool> {
+        let active_high = self.configured_active_high.get().unwrap_or(false);
+        Ok(self.active.get() == active_high)
+    }
+
+    /// Never blocks: the wait "times out" immediately unless a line is active.
+    fn wait(lines: &mut [Self], _timeout: Option<Duration>) -> Result<bool> {
+        Ok(lines.iter().any(|line| line.active.get()))
+    }
 }
@@ -1,17 +1,32 @@
 //! Various flavours of "Pin" that the I/O Expander GPIO ports support.
 //!
 //! * [`InputPin`] - GPIO input that may either be high impedance or have an internal
-//!   pull-up resistor connected.
+//!   pull-up resistor connected, and whose polarity may be inverted.
 //! * [`OutputPin`] - GPIO output that can be initialised to high or low [`Level`].
 //!
+//! Any set of [`OutputPin`]s on the same device can be combined into a [`PinGroup`] so
+//! that they are all updated together.
+//!
+//! To take over a device that is already running, a [`Pin`] can instead be attached as
+//! whichever flavour its registers say it already is, giving an [`AttachedPin`],
+//! without anything being written to the device.
+//!
+//! With the `embedded-hal` feature enabled, the pins also implement the
+//! `embedded_hal::digital` traits so that they can be handed to any driver written
+//! against embedded-hal 1.0.
+//!
 //! # Acknowledgements
 //!
 //! The design of this module is heavily influenced by the
 //! [RPPAL GPIO design](https://github.com/golemparts/rppal/blob/master/src/gpio.rs)
 
-use std::{cell::RefCell, fmt, ops::Not, rc::Rc};
+use std::{fmt, ops::Not};
+
+use rppal::spi::Spi;
 
-use super::{Mcp23s17State, Port, RegisterAddress, Result};
+use super::{
+    MaybeSend, Mcp23s17Error, Mcp23s17State, Port, RegisterAddress, Result, Shared, Transport,
+};
 
 // There is a lot of repetitious code in each of the flavours of [`Pin`] so use macros
 // to reduce that complexity.
@@ -176,10 +191,10 @@ impl fmt::Display for InterruptMode {
 /// An instance of a [`Pin`] can be converted into a configured pin type using one of the
 /// `into_*` methods that consume the [`Pin`] and return the specific pin type.
 #[derive(Debug)]
-pub struct Pin {
+pub struct Pin<T: Transport = Spi> {
     port: Port,
     pub(crate) pin: u8,
-    mcp23s17_state: Rc<RefCell<Mcp23s17State>>,
+    mcp23s17_state: Shared<Mcp23s17State<T>>,
 }
 
 /// A pin on a GPIO port configured for input.
@@ -188,25 +203,100 @@ pub struct Pin {
 /// logic gate) or an input with a pull-up for use with switch inputs. Use the
 /// appropriate method on the [`Pin`]: [`Pin::into_input_pin`] or
 /// [`Pin::into_pullup_input_pin`].
+///
+/// Either flavour can have its polarity inverted (_e.g._ for an active-low switch) so
+/// that it reports the logical level rather than the electrical level. Use
+/// [`Pin::into_inverted_input_pin`], [`Pin::into_inverted_pullup_input_pin`] or
+/// [`InputPin::set_inverted`].
 #[derive(Debug)]
-pub struct InputPin {
-    pin: Pin,
-    /// Whether interrupts are enabled - controls `Drop` behaviour.
-    interrupts_enabled: bool,
+pub struct InputPin<T: Transport = Spi> {
+    pin: Pin<T>,
+    /// Whether the pull-up is connected.
+    mode: InputPinMode,
+    /// The interrupt mode - controls `Drop` behaviour if interrupts are enabled.
+    interrupt_mode: InterruptMode,
+    /// Whether the input polarity is inverted - controls `Drop` behaviour.
+    inverted: bool,
 }
 
 /// A pin on a GPIO port configured for output.
 #[derive(Debug)]
-pub struct OutputPin {
-    pin: Pin,
+pub struct OutputPin<T: Transport = Spi> {
+    pin: Pin<T>,
+}
+
+/// A pin attached as the flavour that the device's registers already configure it
+/// as, returned by [`Pin::into_attached_pin()`].
+#[derive(Debug)]
+pub enum AttachedPin<T: Transport = Spi> {
+    /// The pin is an input (its `IODIR` bit is set).
+    Input(InputPin<T>),
+    /// The pin is an output, along with the [`Level`] that its output latch (`OLAT`)
+    /// is driving it to.
+    Output(OutputPin<T>, Level),
 }
 
-impl Pin {
+/// The configuration registers of a port, as read when attaching to its pins.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PortConfiguration {
+    iodir: u8,
+    gppu: u8,
+    ipol: u8,
+    gpinten: u8,
+    intcon: u8,
+    defval: u8,
+    olat: u8,
+}
+
+impl PortConfiguration {
+    /// Read the configuration of the `port` without writing to the device.
+    pub(crate) fn read<T: Transport>(
+        mcp23s17_state: &mut Mcp23s17State<T>,
+        port: Port,
+    ) -> Result<Self> {
+        let registers = match port {
+            Port::GpioA => [
+                RegisterAddress::IODIRA,
+                RegisterAddress::GPPUA,
+                RegisterAddress::IPOLA,
+                RegisterAddress::GPINTENA,
+                RegisterAddress::INTCONA,
+                RegisterAddress::DEFVALA,
+                RegisterAddress::OLATA,
+            ],
+            Port::GpioB => [
+                RegisterAddress::IODIRB,
+                RegisterAddress::GPPUB,
+                RegisterAddress::IPOLB,
+                RegisterAddress::GPINTENB,
+                RegisterAddress::INTCONB,
+                RegisterAddress::DEFVALB,
+                RegisterAddress::OLATB,
+            ],
+        };
+        let mut values = [0u8; 7];
+        for (value, register) in values.iter_mut().zip(registers) {
+            *value = mcp23s17_state.read(register)?;
+        }
+        let [iodir, gppu, ipol, gpinten, intcon, defval, olat] = values;
+        Ok(PortConfiguration {
+            iodir,
+            gppu,
+            ipol,
+            gpinten,
+            intcon,
+            defval,
+            olat,
+        })
+    }
+}
+
+impl<T: Transport> Pin<T> {
     /// Create a new pin that maintains a reference to the MCP23S17.
     ///
     /// Generally this will be converted into a specific kind of Pin (_e.g._ InputPin)
     /// through one of the various `into_xxx()` methods.
-    pub(crate) fn new(port: Port, pin: u8, mcp23s17_state: Rc<RefCell<Mcp23s17State>>) -> Pin {
+    pub(crate) fn new(port: Port, pin: u8, mcp23s17_state: Shared<Mcp23s17State<T>>) -> Pin<T> {
         Pin {
             port,
             pin,
@@ -218,10 +308,10 @@ impl Pin {
     pub fn read(&self) -> Result<Level> {
         match self.port {
             Port::GpioA => Ok(Level::from(
-                self.mcp23s17_state.borrow().read(RegisterAddress::GPIOA)? & (0x01 << self.pin),
+                self.mcp23s17_state.lock().read(RegisterAddress::GPIOA)? & (0x01 << self.pin),
             )),
             Port::GpioB => Ok(Level::from(
-                self.mcp23s17_state.borrow().read(RegisterAddress::GPIOB)? & (0x01 << self.pin),
+                self.mcp23s17_state.lock().read(RegisterAddress::GPIOB)? & (0x01 << self.pin),
             )),
         }
     }
@@ -230,26 +320,89 @@ impl Pin {
     ///
     /// The InputPin is high-impedance (does not have internal pull-up resistor
     /// connected).
-    pub fn into_input_pin(self) -> Result<InputPin> {
-        InputPin::new(self, InputPinMode::HighImpedance)
+    pub fn into_input_pin(self) -> Result<InputPin<T>> {
+        InputPin::new(self, InputPinMode::HighImpedance, false)
     }
 
     /// Turn the unconfigured `Pin` into an `InputPin` consuming the `Pin` in the process.
     ///
     /// The InputPin has internal pull-up resistor connected.
-    pub fn into_pullup_input_pin(self) -> Result<InputPin> {
-        InputPin::new(self, InputPinMode::PullUp)
+    pub fn into_pullup_input_pin(self) -> Result<InputPin<T>> {
+        InputPin::new(self, InputPinMode::PullUp, false)
+    }
+
+    /// Turn the unconfigured `Pin` into an `InputPin` consuming the `Pin` in the process.
+    ///
+    /// The InputPin is high-impedance and its polarity is inverted.
+    pub fn into_inverted_input_pin(self) -> Result<InputPin<T>> {
+        InputPin::new(self, InputPinMode::HighImpedance, true)
+    }
+
+    /// Turn the unconfigured `Pin` into an `InputPin` consuming the `Pin` in the process.
+    ///
+    /// The InputPin has internal pull-up resistor connected and its polarity is
+    /// inverted, which suits a switch that connects the pin to ground.
+    pub fn into_inverted_pullup_input_pin(self) -> Result<InputPin<T>> {
+        InputPin::new(self, InputPinMode::PullUp, true)
+    }
+
+    /// Turn the unconfigured `Pin` into an `InputPin` or `OutputPin`, according to how
+    /// the device is already configured, consuming the `Pin` in the process.
+    ///
+    /// The pin's `IODIR`, `GPPU`, `IPOL`, `GPINTEN`, `INTCON`, `DEFVAL` and `OLAT`
+    /// bits are read but nothing is written, so an output carries on driving the same
+    /// level. An attached `InputPin` reports its pull-up, inversion and interrupt mode
+    /// and, like any other `InputPin`, disables its interrupts and inversion when
+    /// dropped. No interrupt callback is registered for it.
+    ///
+    /// See [`Mcp23s17::attach_pins()`][super::Mcp23s17::attach_pins] to attach all of
+    /// the device's pins at once.
+    pub fn into_attached_pin(self) -> Result<AttachedPin<T>> {
+        let configuration = PortConfiguration::read(&mut self.mcp23s17_state.lock(), self.port)?;
+        Ok(self.attach(&configuration))
+    }
+
+    /// Turn the `Pin` into an `InputPin` or `OutputPin` that reflects the port's
+    /// `configuration`.
+    pub(crate) fn attach(self, configuration: &PortConfiguration) -> AttachedPin<T> {
+        let bit = |register: u8| register & (0x01 << self.pin) != 0;
+        if !bit(configuration.iodir) {
+            let level = Level::from(bit(configuration.olat));
+            return AttachedPin::Output(OutputPin { pin: self }, level);
+        }
+        let mode = if bit(configuration.gppu) {
+            InputPinMode::PullUp
+        } else {
+            InputPinMode::HighImpedance
+        };
+        let interrupt_mode = match (
+            bit(configuration.gpinten),
+            bit(configuration.intcon),
+            bit(configuration.defval),
+        ) {
+            (false, _, _) => InterruptMode::None,
+            (true, false, _) => InterruptMode::BothEdges,
+            (true, true, false) => InterruptMode::ActiveHigh,
+            (true, true, true) => InterruptMode::ActiveLow,
+        };
+        let inverted = bit(configuration.ipol);
+        AttachedPin::Input(InputPin {
+            pin: self,
+            mode,
+            interrupt_mode,
+            inverted,
+        })
     }
 
     /// Turn the unconfigured `Pin` into an `OutputPin` consuming the `Pin` in the process.
-    pub fn into_output_pin(self) -> Result<OutputPin> {
+    pub fn into_output_pin(self) -> Result<OutputPin<T>> {
         OutputPin::new(self)
     }
 
     /// Turn the unconfigured `Pin` into an `OutputPin` consuming the `Pin` in the process.
     ///
     /// Initialise the pin to be high.
-    pub fn into_output_pin_high(self) -> Result<OutputPin> {
+    pub fn into_output_pin_high(self) -> Result<OutputPin<T>> {
         let pin = OutputPin::new(self)?;
         pin.set_high()?;
         Ok(pin)
@@ -258,30 +411,45 @@ impl Pin {
     /// Turn the unconfigured `Pin` into an `OutputPin` consuming the `Pin` in the process.
     ///
     /// Initialise the pin to be low.
-    pub fn into_output_pin_low(self) -> Result<OutputPin> {
+    pub fn into_output_pin_low(self) -> Result<OutputPin<T>> {
         let pin = OutputPin::new(self)?;
         pin.set_low()?;
         Ok(pin)
     }
 }
 
-impl Drop for Pin {
+impl<T: Transport> Drop for Pin<T> {
     fn drop(&mut self) {
-        self.mcp23s17_state.borrow_mut().gpioa_pins_taken[self.pin as usize] = false;
+        let mut mcp23s17_state = self.mcp23s17_state.lock();
+        mcp23s17_state.pins_taken(self.port)[self.pin as usize] = false;
+        mcp23s17_state
+            .interrupt_dispatcher
+            .set(self.port, self.pin, None);
     }
 }
 
-impl InputPin {
+impl<T: Transport> InputPin<T> {
     /// Constructs an `InputPin` consuming the unconfigured `Pin` in the process.
     ///
     /// Sets the direction of the appropriate GPIO line and configuration of the Pull-up
-    /// control register.
-    fn new(pin: Pin, mode: InputPinMode) -> Result<Self> {
+    /// control register. If the input is `inverted`, the polarity register is set first
+    /// so that the input never reports the wrong level.
+    fn new(pin: Pin<T>, mode: InputPinMode, inverted: bool) -> Result<Self> {
         // Set the direction of the GPIO port.
         // Need to scope to drop the reference to the MCP23S17 state before we move the
         // pin into the return value.
         {
-            let mcp23s17_state = pin.mcp23s17_state.borrow();
+            let mut mcp23s17_state = pin.mcp23s17_state.lock();
+            if inverted {
+                mcp23s17_state.set_bit(
+                    if pin.port == Port::GpioA {
+                        RegisterAddress::IPOLA
+                    } else {
+                        RegisterAddress::IPOLB
+                    },
+                    pin.pin,
+                )?;
+            }
             mcp23s17_state.set_bit(
                 if pin.port == Port::GpioA {
                     RegisterAddress::IODIRA
@@ -313,10 +481,90 @@ impl InputPin {
         }
         Ok(InputPin {
             pin,
-            interrupts_enabled: false,
+            mode,
+            interrupt_mode: InterruptMode::None,
+            inverted,
         })
     }
 
+    /// Set the [`InputPin`] to the requested interrupt `mode` and register a `callback`
+    /// to be called with the captured [`Level`] whenever it raises an interrupt.
+    ///
+    /// The callback is called from
+    /// [`Mcp23s17::service_interrupts()`][super::Mcp23s17::service_interrupts], which
+    /// the application calls when the MCP23S17 signals an interrupt. Any previously
+    /// registered callback is replaced. If the interrupt mode can't be written then no
+    /// callback is left registered.
+    ///
+    /// With the `sync` feature the callback must be [`Send`] so that it can move
+    /// between threads along with the device.
+    pub fn set_interrupt_callback<C>(&mut self, mode: InterruptMode, callback: C) -> Result<()>
+    where
+        C: FnMut(Level) + MaybeSend + 'static,
+    {
+        // Register the callback first so that no interrupt raised as soon as the mode
+        // is written can be missed.
+        self.pin.mcp23s17_state.lock().interrupt_dispatcher.set(
+            self.pin.port,
+            self.pin.pin,
+            Some(Box::new(callback)),
+        );
+        if let Err(error) = self.set_interrupt_mode(mode) {
+            self.pin.mcp23s17_state.lock().interrupt_dispatcher.set(
+                self.pin.port,
+                self.pin.pin,
+                None,
+            );
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Disable interrupts on the [`InputPin`] and remove its callback.
+    pub fn clear_interrupt_callback(&mut self) -> Result<()> {
+        self.pin
+            .mcp23s17_state
+            .lock()
+            .interrupt_dispatcher
+            .set(self.pin.port, self.pin.pin, None);
+        self.set_interrupt_mode(InterruptMode::None)
+    }
+
+    /// Set whether the [`InputPin`]'s polarity is inverted.
+    ///
+    /// When inverted, the `IPOL` register makes the device invert the level of the pin
+    /// so that [`InputPin::read()`], [`InputPin::is_high()`] _etc._, the interrupt
+    /// capture and the [`InterruptMode`] comparisons all work with the logical level.
+    pub fn set_inverted(&mut self, inverted: bool) -> Result<()> {
+        let ipol = match self.pin.port {
+            Port::GpioA => RegisterAddress::IPOLA,
+            Port::GpioB => RegisterAddress::IPOLB,
+        };
+        let mut mcp23s17_state = self.pin.mcp23s17_state.lock();
+        if inverted {
+            mcp23s17_state.set_bit(ipol, self.pin.pin)?;
+        } else {
+            mcp23s17_state.clear_bit(ipol, self.pin.pin)?;
+        }
+        self.inverted = inverted;
+        Ok(())
+    }
+
+    /// Whether the [`InputPin`]'s polarity is inverted.
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Whether the [`InputPin`] has its pull-up connected.
+    pub fn mode(&self) -> InputPinMode {
+        self.mode
+    }
+
+    /// The [`InputPin`]'s current interrupt mode.
+    pub fn interrupt_mode(&self) -> InterruptMode {
+        self.interrupt_mode
+    }
+
     /// Set the [`InputPin`] to the requested `mode` (_i.e._ which edge(s) on the input
     /// trigger an interrupt.)
     ///
@@ -355,26 +603,23 @@ impl InputPin {
         // Set up the registers. Note that GPINTEN is set last so that the correct
         // criteria are set before enabling interrupts to avoid an spurious initial
         // interrupts.
-        let mcp23s17_state = self.pin.mcp23s17_state.borrow();
+        let mut mcp23s17_state = self.pin.mcp23s17_state.lock();
+        self.interrupt_mode = mode;
         match mode {
             InterruptMode::None => {
-                self.interrupts_enabled = false;
                 mcp23s17_state.clear_bit(gpinten, self.pin.pin)?;
             }
             InterruptMode::ActiveHigh => {
-                self.interrupts_enabled = true;
                 mcp23s17_state.set_bit(intcon, self.pin.pin)?;
                 mcp23s17_state.clear_bit(defval, self.pin.pin)?;
                 mcp23s17_state.set_bit(gpinten, self.pin.pin)?;
             }
             InterruptMode::ActiveLow => {
-                self.interrupts_enabled = true;
                 mcp23s17_state.set_bit(intcon, self.pin.pin)?;
                 mcp23s17_state.set_bit(defval, self.pin.pin)?;
                 mcp23s17_state.set_bit(gpinten, self.pin.pin)?;
             }
             InterruptMode::BothEdges => {
-                self.interrupts_enabled = true;
                 mcp23s17_state.clear_bit(intcon, self.pin.pin)?;
                 mcp23s17_state.set_bit(gpinten, self.pin.pin)?;
             }
@@ -385,25 +630,28 @@ impl InputPin {
     impl_input!();
 }
 
-impl Drop for InputPin {
+impl<T: Transport> Drop for InputPin<T> {
     fn drop(&mut self) {
-        if self.interrupts_enabled {
+        if self.interrupt_mode != InterruptMode::None {
             let _ = self.set_interrupt_mode(InterruptMode::None);
         }
+        if self.inverted {
+            let _ = self.set_inverted(false);
+        }
     }
 }
 
-impl OutputPin {
+impl<T: Transport> OutputPin<T> {
     /// Constructs an `OutputPin` consuming the unconfigured `Pin` in the process.
     ///
     /// Sets the direction of the appropriate GPIO line and configuration of the Pull-up
     /// control register.
-    fn new(pin: Pin) -> Result<Self> {
+    fn new(pin: Pin<T>) -> Result<Self> {
         // Set the direction of the GPIO port.
         // Need to scope to drop the reference to the MCP23S17 state before we move the
         // pin into the return value.
         {
-            let mcp23s17_state = pin.mcp23s17_state.borrow();
+            let mut mcp23s17_state = pin.mcp23s17_state.lock();
             mcp23s17_state.clear_bit(
                 if pin.port == Port::GpioA {
                     RegisterAddress::IODIRA
@@ -432,7 +680,7 @@ impl OutputPin {
             Port::GpioA => RegisterAddress::GPIOA,
             Port::GpioB => RegisterAddress::GPIOB,
         };
-        let mcp23s17_state = self.pin.mcp23s17_state.borrow();
+        let mut mcp23s17_state = self.pin.mcp23s17_state.lock();
         match level {
             Level::Low => mcp23s17_state.clear_bit(gpio, self.pin.pin),
             Level::High => mcp23s17_state.set_bit(gpio, self.pin.pin),
@@ -449,6 +697,149 @@ impl OutputPin {
         self.write(Level::Low)
     }
 
+    /// Read the level that the output latch (`OLAT`) is driving the pin to.
+    ///
+    /// Unlike [`OutputPin::read()`] this is not affected by any external load on the
+    /// pin.
+    pub fn read_latch(&self) -> Result<Level> {
+        let olat = match self.pin.port {
+            Port::GpioA => RegisterAddress::OLATA,
+            Port::GpioB => RegisterAddress::OLATB,
+        };
+        self.pin.mcp23s17_state.lock().get_bit(olat, self.pin.pin)
+    }
+
     // Reading from an OutputPin is valid.
     impl_input!();
 }
+
+/// A group of [`OutputPin`]s, on any ports of the same device, that are written
+/// together.
+///
+/// The pins need not be contiguous and can be a mix of [`Port::GpioA`] and
+/// [`Port::GpioB`]. Bit 0 of the value given to [`PinGroup::write_bits()`] is written to
+/// the first pin in the group, bit 1 to the second, and so on.
+///
+/// ```no_run
+/// use rppal_mcp23s17::{ChipSelect, HardwareAddress, Mcp23s17, PinGroup, Port, SpiBus, SpiMode};
+///
+/// let mcp23s17 = Mcp23s17::new(
+///     HardwareAddress::new(0).expect("Invalid hardware address"),
+///     SpiBus::Spi0,
+///     ChipSelect::Cs0,
+///     100_000,
+///     SpiMode::Mode0,
+/// )
+/// .expect("Failed to create MCP23S17");
+///
+/// let pins = [2, 3, 5, 7]
+///     .into_iter()
+///     .map(|pin| mcp23s17.get(Port::GpioA, pin)?.into_output_pin())
+///     .collect::<Result<Vec<_>, _>>()
+///     .expect("Failed to get OutputPins");
+/// let nibble = PinGroup::new(pins).expect("Failed to create PinGroup");
+/// nibble.write_bits(0b1010).expect("Bad write");
+/// ```
+#[derive(Debug)]
+pub struct PinGroup<T: Transport = Spi> {
+    pins: Vec<OutputPin<T>>,
+}
+
+impl<T: Transport> PinGroup<T> {
+    /// Create a group from the `pins`, which must all be on the same device.
+    ///
+    /// Returns [`Mcp23s17Error::InvalidPinGroup`] if there are no pins or they are on
+    /// more than one device.
+    pub fn new(pins: Vec<OutputPin<T>>) -> Result<Self> {
+        let Some(first) = pins.first() else {
+            return Err(Mcp23s17Error::InvalidPinGroup);
+        };
+        if !pins
+            .iter()
+            .all(|pin| pin.pin.mcp23s17_state.ptr_eq(&first.pin.mcp23s17_state))
+        {
+            return Err(Mcp23s17Error::InvalidPinGroup);
+        }
+        Ok(PinGroup { pins })
+    }
+
+    /// Write bit _n_ of `bits` to the _n_th pin of the group.
+    ///
+    /// The output latches are updated with a single read-modify-write so that all the
+    /// pins change at the same instant, with no intermediate states visible. If the
+    /// group spans both ports then both latches are written in one transfer, as for
+    /// [`Mcp23s17::modify_olat16()`][super::Mcp23s17::modify_olat16] (except in
+    /// [`BankMode::Segregated`][super::BankMode::Segregated], where two transfers are
+    /// needed).
+    pub fn write_bits(&self, bits: u16) -> Result<()> {
+        let mut mask = 0u16;
+        let mut value = 0u16;
+        for (index, pin) in self.pins.iter().enumerate() {
+            let bit = match pin.pin.port {
+                Port::GpioA => 0x0001 << pin.pin.pin,
+                Port::GpioB => 0x0100 << pin.pin.pin,
+            };
+            mask |= bit;
+            if bits & (0x01 << index) != 0 {
+                value |= bit;
+            }
+        }
+
+        let mut mcp23s17_state = self.pins[0].pin.mcp23s17_state.lock();
+        let [mask_a, mask_b] = mask.to_le_bytes();
+        let [value_a, value_b] = value.to_le_bytes();
+        match (mask_a, mask_b) {
+            (_, 0) => mcp23s17_state.modify_bits(RegisterAddress::OLATA, mask_a, value_a),
+            (0, _) => mcp23s17_state.modify_bits(RegisterAddress::OLATB, mask_b, value_b),
+            _ => mcp23s17_state.modify_olat16(mask, value),
+        }
+    }
+
+    /// Consume the group, returning its pins.
+    pub fn into_pins(self) -> Vec<OutputPin<T>> {
+        self.pins
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<T: Transport> embedded_hal::digital::ErrorType for InputPin<T> {
+    type Error = super::Mcp23s17Error;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<T: Transport> embedded_hal::digital::InputPin for InputPin<T> {
+    fn is_high(&mut self) -> Result<bool> {
+        InputPin::is_high(self)
+    }
+
+    fn is_low(&mut self) -> Result<bool> {
+        InputPin::is_low(self)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<T: Transport> embedded_hal::digital::ErrorType for OutputPin<T> {
+    type Error = super::Mcp23s17Error;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<T: Transport> embedded_hal::digital::OutputPin for OutputPin<T> {
+    fn set_low(&mut self) -> Result<()> {
+        OutputPin::set_low(self)
+    }
+
+    fn set_high(&mut self) -> Result<()> {
+        OutputPin::set_high(self)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<T: Transport> embedded_hal::digital::StatefulOutputPin for OutputPin<T> {
+    fn is_set_high(&mut self) -> Result<bool> {
+        Ok(self.read_latch()? == Level::High)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool> {
+        Ok(self.read_latch()? == Level::Low)
+    }
+}
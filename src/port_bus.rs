@@ -0,0 +1,92 @@
+//! A whole GPIO port used as an 8-bit parallel bus.
+//!
+//! A [`PortBus`] claims all eight pins of a [`Port`] at once so that 8-bit parallel
+//! peripherals can be driven with a single register access per operation rather than
+//! through eight separate [`Pin`][crate::Pin]s. Bit 0 of each value is the pin `GPx0`
+//! through to bit 7 being `GPx7`.
+
+use rppal::spi::Spi;
+
+use super::{Mcp23s17State, Port, RegisterAddress, Result, Shared, Transport};
+
+/// All eight pins of a GPIO port used together as a bus.
+///
+/// Acquired through [`Mcp23s17::get_port_bus()`][crate::Mcp23s17::get_port_bus]. The
+/// pins are released when the `PortBus` goes out of scope.
+#[derive(Debug)]
+pub struct PortBus<T: Transport = Spi> {
+    port: Port,
+    mcp23s17_state: Shared<Mcp23s17State<T>>,
+}
+
+impl<T: Transport> PortBus<T> {
+    /// Create a new bus over the `port`, whose pins must already have been marked as
+    /// taken, and configure its direction and pull-ups.
+    pub(crate) fn new(
+        port: Port,
+        direction: u8,
+        pullups: u8,
+        mcp23s17_state: Shared<Mcp23s17State<T>>,
+    ) -> Result<Self> {
+        // Create the bus before configuring it so that the pins are released again if
+        // the configuration fails.
+        let port_bus = PortBus {
+            port,
+            mcp23s17_state,
+        };
+        port_bus.set_pullups(pullups)?;
+        port_bus.set_direction(direction)?;
+        Ok(port_bus)
+    }
+
+    /// Read the logic levels of all the pins on the port.
+    pub fn read(&self) -> Result<u8> {
+        let gpio = match self.port {
+            Port::GpioA => RegisterAddress::GPIOA,
+            Port::GpioB => RegisterAddress::GPIOB,
+        };
+        self.mcp23s17_state.lock().read(gpio)
+    }
+
+    /// Write the levels of all the pins on the port.
+    ///
+    /// Only the pins configured as outputs are driven; the bits for input pins are held
+    /// in the output latch until the pin becomes an output.
+    pub fn write(&self, value: u8) -> Result<()> {
+        let gpio = match self.port {
+            Port::GpioA => RegisterAddress::GPIOA,
+            Port::GpioB => RegisterAddress::GPIOB,
+        };
+        self.mcp23s17_state.lock().write(gpio, value)
+    }
+
+    /// Set the direction of the pins on the port: a `1` bit makes the pin an input and a
+    /// `0` bit makes it an output.
+    pub fn set_direction(&self, direction: u8) -> Result<()> {
+        let iodir = match self.port {
+            Port::GpioA => RegisterAddress::IODIRA,
+            Port::GpioB => RegisterAddress::IODIRB,
+        };
+        self.mcp23s17_state.lock().write(iodir, direction)
+    }
+
+    /// Set which pins on the port have their internal pull-up resistor connected.
+    pub fn set_pullups(&self, pullups: u8) -> Result<()> {
+        let gppu = match self.port {
+            Port::GpioA => RegisterAddress::GPPUA,
+            Port::GpioB => RegisterAddress::GPPUB,
+        };
+        self.mcp23s17_state.lock().write(gppu, pullups)
+    }
+
+    /// Gets the port that the bus is on.
+    pub fn get_port(&self) -> Port {
+        self.port
+    }
+}
+
+impl<T: Transport> Drop for PortBus<T> {
+    fn drop(&mut self) {
+        self.mcp23s17_state.lock().pins_taken(self.port).fill(false);
+    }
+}
@@ -0,0 +1,110 @@
+//! Advisory locking of the device between processes.
+//!
+//! The bit operations (_e.g._ [`Mcp23s17::set_bit()`][crate::Mcp23s17::set_bit]) are
+//! a read-modify-write of a register, so two processes driving pins in the same
+//! register can lose each other's changes. Cooperating processes that enable a
+//! [`ProcessLock`] on the same lock file take an exclusive `flock(2)` on it around
+//! each read-modify-write so that they cannot interleave.
+//!
+//! ```no_run
+//! use rppal_mcp23s17::{ChipSelect, HardwareAddress, Mcp23s17, ProcessLock, SpiBus, SpiMode};
+//!
+//! let address = HardwareAddress::new(0).expect("Invalid hardware address");
+//! let mcp23s17 = Mcp23s17::new(address, SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0)
+//!     .expect("Failed to create MCP23S17");
+//! mcp23s17
+//!     .enable_process_lock(ProcessLock::default_path(SpiBus::Spi0, ChipSelect::Cs0, address))
+//!     .expect("Failed to open lock file");
+//! ```
+//!
+//! The lock is advisory: it only protects against processes that use the same lock
+//! file.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use super::{ChipSelect, HardwareAddress, Result, SpiBus};
+
+/// An open lock file shared by the processes accessing one device.
+#[derive(Debug)]
+pub struct ProcessLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl ProcessLock {
+    /// The directory holding the default lock files.
+    pub const DEFAULT_DIRECTORY: &'static str = "/run/lock";
+
+    /// Open (creating if necessary) the lock file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        debug!("Open process lock {}", path.display());
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        Ok(ProcessLock { file, path })
+    }
+
+    /// The conventional lock file for the device at the hardware `address` on the
+    /// `spi_bus` and `chip_select`, in [`ProcessLock::DEFAULT_DIRECTORY`].
+    pub fn default_path(
+        spi_bus: SpiBus,
+        chip_select: ChipSelect,
+        address: HardwareAddress,
+    ) -> PathBuf {
+        Path::new(Self::DEFAULT_DIRECTORY).join(format!(
+            "rppal-mcp23s17-{spi_bus:?}-{chip_select:?}-{address}.lock"
+        ))
+    }
+
+    /// The path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Take the exclusive lock, blocking until it is available. The lock is released
+    /// when the returned guard is dropped.
+    pub(crate) fn lock(&self) -> Result<ProcessLockGuard<'_>> {
+        flock(&self.file, libc::LOCK_EX)?;
+        Ok(ProcessLockGuard { file: &self.file })
+    }
+}
+
+/// Releases the lock taken by [`ProcessLock::lock()`] when dropped.
+#[derive(Debug)]
+pub(crate) struct ProcessLockGuard<'a> {
+    file: &'a File,
+}
+
+impl Drop for ProcessLockGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(error) = flock(self.file, libc::LOCK_UN) {
+            log::error!("Failed to release process lock: {error}");
+        }
+    }
+}
+
+/// Apply the `flock(2)` `operation` to the `file`, retrying if interrupted by a signal.
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    loop {
+        // SAFETY: flock only operates on the file descriptor, which stays open whilst
+        // the `file` is borrowed.
+        if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! The bookkeeping of a device's registers that is independent of how they are
+//! transferred.
+//!
+//! The blocking [`Mcp23s17`][crate::Mcp23s17] and the asynchronous
+//! `asynch::Mcp23s17` each keep a [`RegisterMap`] alongside their transport and
+//! consult it around every transfer: it maps each [`RegisterAddress`] onto the
+//! device's [`RegisterLayout`], follows the `IOCON` register (including the switch of
+//! layout when its `BANK` bit changes), holds the shadow register cache and the
+//! process lock.
+
+#[cfg(feature = "process-lock")]
+use std::sync::Arc;
+
+use log::{debug, error};
+
+#[cfg(feature = "process-lock")]
+use super::process_lock::ProcessLock;
+use super::{BankMode, IOCON, Mcp23s17Error, RegisterAddress, RegisterLayout, Result};
+
+/// What is known about the registers of one device.
+#[derive(Debug, Default)]
+pub(crate) struct RegisterMap {
+    /// How the registers are laid out in the device.
+    pub(crate) layout: RegisterLayout,
+
+    /// The value of `IOCON` last written to, or read from, the device. Until then the
+    /// power-on default is assumed.
+    pub(crate) iocon: IOCON,
+
+    /// Whether the device shares its chip select with others on an
+    /// [`Mcp23s17Bus`][crate::Mcp23s17Bus], so must keep [`IOCON::HAEN`] set.
+    pub(crate) hardware_addressed: bool,
+
+    /// Shadow copy of the cacheable registers, if the cache is enabled.
+    pub(crate) cache: Option<[u8; RegisterAddress::LENGTH]>,
+
+    /// The lock shared with other processes, if enabled.
+    #[cfg(feature = "process-lock")]
+    pub(crate) process_lock: Option<Arc<ProcessLock>>,
+}
+
+impl RegisterMap {
+    /// The physical address of the `register` in the current layout.
+    pub(crate) fn address(&self, register: RegisterAddress) -> Result<u8> {
+        self.layout.address(register)
+    }
+
+    /// The bank mode implied by the register layout.
+    pub(crate) fn bank_mode(&self) -> BankMode {
+        match self.layout {
+            RegisterLayout::Banked => BankMode::Segregated,
+            RegisterLayout::Interleaved | RegisterLayout::SinglePort => BankMode::Interleaved,
+        }
+    }
+
+    /// Take the device to already be in `bank_mode`.
+    pub(crate) fn assume_bank_mode(&mut self, bank_mode: BankMode) -> Result<()> {
+        self.check_bankable()?;
+        debug!("Assume bank mode {bank_mode}");
+        self.layout = RegisterLayout::for_bank_mode(bank_mode);
+        self.iocon
+            .set(IOCON::BANK, bank_mode == BankMode::Segregated);
+        Ok(())
+    }
+
+    /// Check that the device has a `BANK` bit, which the single-port devices do not.
+    pub(crate) fn check_bankable(&self) -> Result<()> {
+        if self.layout == RegisterLayout::SinglePort {
+            return Err(Mcp23s17Error::UnsupportedConfiguration(
+                "BANK on a single-port device",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the `B` register of a pair can be accessed in the same transfer as the
+    /// `A` register.
+    ///
+    /// In the interleaved layout the device's address pointer moves from the `A`
+    /// register to the `B` register whether it increments (sequential operation) or,
+    /// with [`IOCON::SEQOP`] set, toggles within the pair. In the banked layout it
+    /// would run on to the next `A` register or, with `SEQOP` set, stay put, so the
+    /// registers are accessed in separate transfers.
+    pub(crate) fn pair_in_one_transfer(&self) -> bool {
+        match self.layout {
+            RegisterLayout::Interleaved => true,
+            RegisterLayout::Banked | RegisterLayout::SinglePort => false,
+        }
+    }
+
+    /// Physical address of the first register of a burst of `length` registers from
+    /// `start`, checking that the burst stays within the device's registers.
+    ///
+    /// With [`IOCON::SEQOP`] set the address pointer doesn't increment: in the
+    /// interleaved layout it toggles within the `A`/`B` pair and otherwise it stays
+    /// put, so a burst may only run on from an `A` register to its `B` register.
+    pub(crate) fn burst_address(&self, start: RegisterAddress, length: usize) -> Result<u8> {
+        let address = self.layout.address(start)?;
+        if usize::from(address) + length > self.layout.burst_end(address) {
+            error!("Burst of {length} registers from {start:?} is out of range!");
+            return Err(Mcp23s17Error::RegisterAddressBoundsError);
+        }
+        if self.iocon.contains(IOCON::SEQOP) {
+            let pair_length = match self.layout {
+                RegisterLayout::Interleaved if address % 2 == 0 => 2,
+                _ => 1,
+            };
+            if length > pair_length {
+                error!("Burst of {length} registers from {start:?} without sequential operation!");
+                return Err(Mcp23s17Error::UnsupportedConfiguration(
+                    "burst beyond a register pair with SEQOP set",
+                ));
+            }
+        }
+        Ok(address)
+    }
+
+    /// The values to write in a burst from the physical `address`, checking that the
+    /// burst doesn't change the bank mode part way through.
+    pub(crate) fn burst_to_write(
+        &self,
+        start: RegisterAddress,
+        address: u8,
+        data: &[u8],
+    ) -> Result<Vec<u8>> {
+        (address..)
+            .zip(data)
+            .map(|(offset, &value)| {
+                let register = self.layout.register(offset)?;
+                let value = self.value_to_write(register, value);
+                if matches!(register, RegisterAddress::IOCON | RegisterAddress::IOCON2)
+                    && self.layout != RegisterLayout::SinglePort
+                    && IOCON::from_bits_retain(value).contains(IOCON::BANK)
+                        != (self.bank_mode() == BankMode::Segregated)
+                {
+                    error!("Burst write from {start:?} would change the bank mode");
+                    return Err(Mcp23s17Error::UnsupportedConfiguration(
+                        "BANK change within a burst write",
+                    ));
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+
+    /// The value to write to `register` in place of `data`: a device on an
+    /// [`Mcp23s17Bus`][crate::Mcp23s17Bus] keeps `HAEN` set in `IOCON`, without which
+    /// it would answer to every address.
+    pub(crate) fn value_to_write(&self, register: RegisterAddress, data: u8) -> u8 {
+        if self.hardware_addressed
+            && matches!(register, RegisterAddress::IOCON | RegisterAddress::IOCON2)
+        {
+            data | IOCON::HAEN.bits()
+        } else {
+            data
+        }
+    }
+
+    /// The value of the `register` held in the shadow register cache, if any.
+    pub(crate) fn cached(&self, register: RegisterAddress) -> Result<Option<u8>> {
+        match self.cache {
+            Some(cache) if register.is_cacheable() => {
+                // Still check that the register exists in this device.
+                self.layout.address(register)?;
+                debug!("Read {register:?} (cached)");
+                Ok(Some(cache[register as usize]))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The cached value of the `register` to modify some of its bits, if any.
+    ///
+    /// With the cache enabled, the `GPIO` registers are modified based on the output
+    /// latch so no read transfer is needed. The cache is bypassed if the process lock
+    /// is enabled since other processes may have changed the register.
+    pub(crate) fn cached_for_modify(&self, register: RegisterAddress) -> Result<Option<u8>> {
+        #[cfg(feature = "process-lock")]
+        if self.process_lock.is_some() {
+            return Ok(None);
+        }
+        match (self.cache, register) {
+            (Some(cache), RegisterAddress::GPIOA) => {
+                Ok(Some(cache[RegisterAddress::OLATA as usize]))
+            }
+            (Some(cache), RegisterAddress::GPIOB) => {
+                Ok(Some(cache[RegisterAddress::OLATB as usize]))
+            }
+            _ => self.cached(register),
+        }
+    }
+
+    /// The cached value of both `OLAT` registers to modify some of their bits, if any.
+    pub(crate) fn cached_olat16_for_modify(&self) -> Option<u16> {
+        #[cfg(feature = "process-lock")]
+        if self.process_lock.is_some() {
+            return None;
+        }
+        self.cache.map(|cache| {
+            u16::from_le_bytes([
+                cache[RegisterAddress::OLATA as usize],
+                cache[RegisterAddress::OLATB as usize],
+            ])
+        })
+    }
+
+    /// The registers to load into the shadow register cache, if it is enabled.
+    pub(crate) fn registers_to_cache(&self) -> Vec<RegisterAddress> {
+        if self.cache.is_none() {
+            return Vec::new();
+        }
+        (0..RegisterAddress::LENGTH)
+            .filter_map(|index| RegisterAddress::try_from(index).ok())
+            .filter(|&register| register.is_cacheable() && self.layout.address(register).is_ok())
+            .collect()
+    }
+
+    /// Record a value read from the device.
+    pub(crate) fn track_read(&mut self, register: RegisterAddress, data: u8) {
+        if matches!(register, RegisterAddress::IOCON | RegisterAddress::IOCON2) {
+            self.iocon = IOCON::from_bits_retain(data);
+        }
+    }
+
+    /// Record a value written to the device in the shadow register cache (if enabled)
+    /// and follow the device's `IOCON`, including the switch of register layout when
+    /// the `BANK` bit changes.
+    pub(crate) fn track_write(&mut self, register: RegisterAddress, data: u8) {
+        if let Some(cache) = self.cache.as_mut() {
+            match register {
+                // Both addresses access the same register.
+                RegisterAddress::IOCON | RegisterAddress::IOCON2 => {
+                    cache[RegisterAddress::IOCON as usize] = data;
+                    cache[RegisterAddress::IOCON2 as usize] = data;
+                }
+                // Writing to GPIO writes the output latch.
+                RegisterAddress::GPIOA => cache[RegisterAddress::OLATA as usize] = data,
+                RegisterAddress::GPIOB => cache[RegisterAddress::OLATB as usize] = data,
+                _ if register.is_cacheable() => cache[register as usize] = data,
+                _ => (),
+            }
+        }
+        if !matches!(register, RegisterAddress::IOCON | RegisterAddress::IOCON2) {
+            return;
+        }
+        self.iocon = IOCON::from_bits_retain(data);
+        if self.layout != RegisterLayout::SinglePort {
+            let bank_mode = if self.iocon.contains(IOCON::BANK) {
+                BankMode::Segregated
+            } else {
+                BankMode::Interleaved
+            };
+            if bank_mode != self.bank_mode() {
+                debug!("Bank mode now {bank_mode}");
+                self.layout = RegisterLayout::for_bank_mode(bank_mode);
+            }
+        }
+    }
+
+    /// The process lock, if enabled, as a handle that leaves the device free to be
+    /// used whilst the lock is held.
+    #[cfg(feature = "process-lock")]
+    pub(crate) fn process_lock(&self) -> Option<Arc<ProcessLock>> {
+        self.process_lock.clone()
+    }
+}
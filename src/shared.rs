@@ -0,0 +1,91 @@
+//! The shared ownership of the device state between the device and its pins.
+//!
+//! By default the state is held in an `Rc<RefCell<_>>` so the device and its pins are
+//! confined to one thread. With the `sync` feature it is held in an `Arc<Mutex<_>>`
+//! instead so that they are `Send` and `Sync`, and each operation on the device
+//! (including the read-modify-write of a bit operation) runs with the mutex locked.
+
+#[cfg(feature = "sync")]
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(not(feature = "sync"))]
+use std::{
+    cell::{RefCell, RefMut},
+    rc::Rc,
+};
+
+/// A value shared between the device and its pins.
+#[cfg(not(feature = "sync"))]
+#[derive(Debug, Default)]
+pub(crate) struct Shared<T>(Rc<RefCell<T>>);
+
+/// A value shared between the device and its pins, possibly on different threads.
+#[cfg(feature = "sync")]
+#[derive(Debug, Default)]
+pub(crate) struct Shared<T>(Arc<Mutex<T>>);
+
+#[cfg(not(feature = "sync"))]
+impl<T> Shared<T> {
+    /// Share the `value`.
+    pub(crate) fn new(value: T) -> Self {
+        Shared(Rc::new(RefCell::new(value)))
+    }
+
+    /// Get exclusive access to the value for the lifetime of the returned guard.
+    pub(crate) fn lock(&self) -> RefMut<'_, T> {
+        self.0.borrow_mut()
+    }
+
+    /// Whether both share the same value.
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T> Shared<T> {
+    /// Share the `value`.
+    pub(crate) fn new(value: T) -> Self {
+        Shared(Arc::new(Mutex::new(value)))
+    }
+
+    /// Get exclusive access to the value for the lifetime of the returned guard.
+    ///
+    /// A panic whilst the value was locked doesn't leave the driver's state
+    /// inconsistent, so the poisoning is ignored.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether both share the same value.
+    pub(crate) fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Shared(self.0.clone())
+    }
+}
+
+/// Implemented by every type that is `Send`, if the `sync` feature is enabled, or
+/// otherwise by every type.
+///
+/// Used to require that the interrupt callbacks can be moved between threads along
+/// with the device only when the `sync` feature is enabled.
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+
+#[cfg(feature = "sync")]
+impl<T: Send> MaybeSend for T {}
+
+/// Implemented by every type that is `Send`, if the `sync` feature is enabled, or
+/// otherwise by every type.
+///
+/// Used to require that the interrupt callbacks can be moved between threads along
+/// with the device only when the `sync` feature is enabled.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "sync"))]
+impl<T> MaybeSend for T {}
@@ -1,8 +1,48 @@
+use std::{
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
 use super::*;
+use mock_spi::MockInterruptLine;
+
+/// A transport to a mock device whose transfers can be made to fail.
+#[derive(Debug)]
+struct FailingTransport {
+    mock_spi: MockSpi,
+    failing: Arc<AtomicBool>,
+}
+
+impl FailingTransport {
+    /// Create the transport along with the switch that makes its transfers fail.
+    fn new() -> (Self, Arc<AtomicBool>) {
+        let failing = Arc::new(AtomicBool::new(false));
+        let transport = FailingTransport {
+            mock_spi: MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0),
+            failing: failing.clone(),
+        };
+        (transport, failing)
+    }
+}
+
+impl Transport for FailingTransport {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(Mcp23s17Error::TransportError {
+                source: Box::new(io::Error::other("Transfer failed")),
+            });
+        }
+        self.mock_spi.transfer(read_buffer, write_buffer)
+    }
+}
 
 #[test]
 fn set_bits() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -23,7 +63,7 @@ fn set_bits() {
 
 #[test]
 fn clear_bits() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -44,7 +84,7 @@ fn clear_bits() {
 
 #[test]
 fn set_bit() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -65,7 +105,7 @@ fn set_bit() {
 
 #[test]
 fn clear_bit() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -86,7 +126,7 @@ fn clear_bit() {
 
 #[test]
 fn get_bit() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -112,7 +152,7 @@ fn get_bit() {
 
 #[test]
 fn input_pin_disable_interrupts_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -123,7 +163,7 @@ fn input_pin_disable_interrupts_gpioa() {
     {
         // Put data into IODIRA, GPPUA, GPINTENA, INTCONA and DEFVALA that let us
         // observe the operation of the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPINTENA, 0b1111_1111);
@@ -138,7 +178,7 @@ fn input_pin_disable_interrupts_gpioa() {
         .expect("Bad mode set");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1000_0000, 1, 1),
@@ -158,7 +198,7 @@ fn input_pin_disable_interrupts_gpioa() {
 
 #[test]
 fn input_pin_enable_interrupts_rising_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -169,7 +209,7 @@ fn input_pin_enable_interrupts_rising_gpioa() {
     {
         // Put data into IODIRA, GPPUA, GPINTENA, INTCONA and DEFVALA that let us
         // observe the operation of the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPINTENA, 0b0000_0000);
@@ -186,7 +226,7 @@ fn input_pin_enable_interrupts_rising_gpioa() {
         .expect("Bad mode set");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1000_0000, 1, 1),
@@ -216,7 +256,7 @@ fn input_pin_enable_interrupts_rising_gpioa() {
 
 #[test]
 fn input_pin_enable_interrupts_falling_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -227,7 +267,7 @@ fn input_pin_enable_interrupts_falling_gpioa() {
     {
         // Put data into IODIRA, GPPUA, GPINTENA, INTCONA and DEFVALA that let us
         // observe the operation of the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPINTENA, 0b0000_0000);
@@ -244,7 +284,7 @@ fn input_pin_enable_interrupts_falling_gpioa() {
         .expect("Bad mode set");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1000_0000, 1, 1),
@@ -274,7 +314,7 @@ fn input_pin_enable_interrupts_falling_gpioa() {
 
 #[test]
 fn input_pin_enable_interrupts_both_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -285,7 +325,7 @@ fn input_pin_enable_interrupts_both_gpioa() {
     {
         // Put data into IODIRA, GPPUA, GPINTENA, INTCONA and DEFVALA that let us
         // observe the operation of the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPINTENA, 0b0000_0000);
@@ -301,7 +341,7 @@ fn input_pin_enable_interrupts_both_gpioa() {
         .expect("Bad mode set");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1000_0000, 1, 1),
@@ -326,7 +366,7 @@ fn input_pin_enable_interrupts_both_gpioa() {
 
 #[test]
 fn input_pin_enable_interrupts_rising_gpiob() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -337,7 +377,7 @@ fn input_pin_enable_interrupts_rising_gpiob() {
     {
         // Put data into IODIRB, GPPUB, GPINTENB, INTCONB and DEFVALB that let us
         // observe the operation of the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRB, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUB, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPINTENB, 0b0000_0000);
@@ -354,7 +394,7 @@ fn input_pin_enable_interrupts_rising_gpiob() {
         .expect("Bad mode set");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRB),
         (0b1000_0000, 1, 1),
@@ -384,7 +424,7 @@ fn input_pin_enable_interrupts_rising_gpiob() {
 
 #[test]
 fn write_output_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -395,7 +435,7 @@ fn write_output_pin_low_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0001_0000);
@@ -409,7 +449,7 @@ fn write_output_pin_low_gpioa() {
     pin.write(Level::Low).expect("Bad pin write");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1110_1111, 1, 1),
@@ -429,7 +469,7 @@ fn write_output_pin_low_gpioa() {
 
 #[test]
 fn write_output_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -440,7 +480,7 @@ fn write_output_pin_high_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
@@ -454,7 +494,7 @@ fn write_output_pin_high_gpioa() {
     pin.write(Level::High).expect("Bad pin write");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1110_1111, 1, 1),
@@ -474,7 +514,7 @@ fn write_output_pin_high_gpioa() {
 
 #[test]
 fn output_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -485,7 +525,7 @@ fn output_pin_low_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0001_0000);
@@ -498,7 +538,7 @@ fn output_pin_low_gpioa() {
         .expect("Failed to convert to OutputPinLow");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1110_1111, 1, 1),
@@ -518,7 +558,7 @@ fn output_pin_low_gpioa() {
 
 #[test]
 fn output_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -529,7 +569,7 @@ fn output_pin_high_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
@@ -542,7 +582,7 @@ fn output_pin_high_gpioa() {
         .expect("Failed to convert to OutputPinHigh");
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1110_1111, 1, 1),
@@ -562,7 +602,7 @@ fn output_pin_high_gpioa() {
 
 #[test]
 fn write_output_pin_high_gpiob() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -573,7 +613,7 @@ fn write_output_pin_high_gpiob() {
     {
         // Put data into IODIRB and GPPUB and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRB, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUB, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
@@ -587,7 +627,7 @@ fn write_output_pin_high_gpiob() {
     pin.write(Level::High).expect("Bad pin write");
 
     // Check we got the expected values written into IODIRB and GPPUB
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRB),
         (0b1110_1111, 1, 1),
@@ -607,7 +647,7 @@ fn write_output_pin_high_gpiob() {
 
 #[test]
 fn read_output_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -618,7 +658,7 @@ fn read_output_pin_low_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
@@ -633,7 +673,7 @@ fn read_output_pin_low_gpioa() {
     assert_eq!(pin_level, Level::Low);
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b1110_1111, 1, 1),
@@ -648,7 +688,7 @@ fn read_output_pin_low_gpioa() {
 
 #[test]
 fn read_output_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -659,7 +699,7 @@ fn read_output_pin_high_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the Pin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0001_0000);
@@ -676,7 +716,7 @@ fn read_output_pin_high_gpioa() {
 
 #[test]
 fn read_input_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -687,7 +727,7 @@ fn read_input_pin_low_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the InputPin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
@@ -702,7 +742,7 @@ fn read_input_pin_low_gpioa() {
     assert_eq!(pin_level, Level::Low);
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b0000_0001, 1, 1),
@@ -717,7 +757,7 @@ fn read_input_pin_low_gpioa() {
 
 #[test]
 fn read_input_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -728,7 +768,7 @@ fn read_input_pin_high_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the InputPin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b1111_1111);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0001);
@@ -745,7 +785,7 @@ fn read_input_pin_high_gpioa() {
 
 #[test]
 fn read_pullup_input_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -756,7 +796,7 @@ fn read_pullup_input_pin_low_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the InputPin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
@@ -771,7 +811,7 @@ fn read_pullup_input_pin_low_gpioa() {
     assert_eq!(pin_level, Level::Low);
 
     // Check we got the expected values written into IODIRA and GPPUA
-    let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+    let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
     assert_eq!(
         mock_spi.get_mock_data(RegisterAddress::IODIRA),
         (0b0000_0001, 1, 1),
@@ -786,7 +826,7 @@ fn read_pullup_input_pin_low_gpioa() {
 
 #[test]
 fn read_pullup_input_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -797,7 +837,7 @@ fn read_pullup_input_pin_high_gpioa() {
     {
         // Put data into IODIRA and GPPUA and that lets us observe the operation of
         // the InputPin.
-        let mock_spi = &mcp23s17.mcp23s17_state.borrow().spi;
+        let mock_spi = &mcp23s17.mcp23s17_state.lock().spi;
         mock_spi.set_mock_data(RegisterAddress::IODIRA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPPUA, 0b0000_0000);
         mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0001);
@@ -813,7 +853,7 @@ fn read_pullup_input_pin_high_gpioa() {
 
 #[test]
 fn read_pin_low_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -829,7 +869,7 @@ fn read_pin_low_gpioa() {
 
 #[test]
 fn read_pin_high_gpioa() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -845,7 +885,7 @@ fn read_pin_high_gpioa() {
 
 #[test]
 fn read_pin_high_gpiob() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -861,7 +901,7 @@ fn read_pin_high_gpiob() {
 
 #[test]
 fn create_unique_pin() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -874,7 +914,7 @@ fn create_unique_pin() {
 
 #[test]
 fn create_out_of_range_pin() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -892,7 +932,7 @@ fn create_out_of_range_pin() {
 
 #[test]
 fn create_duplicate_pin() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -915,7 +955,7 @@ fn create_duplicate_pin() {
 
 #[test]
 fn create_duplicate_pins_separate_ports() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -933,7 +973,7 @@ fn create_duplicate_pins_separate_ports() {
 
 #[test]
 fn read_iocon() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -955,7 +995,7 @@ fn read_iocon() {
 
 #[test]
 fn write_iocon() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -994,7 +1034,7 @@ fn iocon() {
 
 #[test]
 fn spi_control_read() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -1005,14 +1045,14 @@ fn spi_control_read() {
 
     let spi_ctrl = mcp23s17
         .mcp23s17_state
-        .borrow()
+        .lock()
         .spi_control_byte(SpiCommand::Read);
     assert_eq!(0x41, spi_ctrl, "Unexpected control byte: 0x{spi_ctrl:02x}");
 }
 
 #[test]
 fn spi_control_write() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(0).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -1022,14 +1062,14 @@ fn spi_control_write() {
     .expect("Create MCP23S17");
     let spi_ctrl = mcp23s17
         .mcp23s17_state
-        .borrow()
+        .lock()
         .spi_control_byte(SpiCommand::Write);
     assert_eq!(0x40, spi_ctrl, "Unexpected control byte: 0x{spi_ctrl:02x}");
 }
 
 #[test]
 fn spi_control_address_read() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(1).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -1039,14 +1079,14 @@ fn spi_control_address_read() {
     .expect("Create MCP23S17");
     let spi_ctrl = mcp23s17
         .mcp23s17_state
-        .borrow()
+        .lock()
         .spi_control_byte(SpiCommand::Read);
     assert_eq!(0x43, spi_ctrl, "Unexpected control byte: 0x{spi_ctrl:02x}");
 }
 
 #[test]
 fn spi_control_address_write() {
-    let mcp23s17 = Mcp23s17::new(
+    let mcp23s17 = Mcp23s17::new_mock(
         HardwareAddress::new(1).unwrap(),
         SpiBus::Spi0,
         ChipSelect::Cs0,
@@ -1056,7 +1096,7 @@ fn spi_control_address_write() {
     .expect("Create MCP23S17");
     let spi_ctrl = mcp23s17
         .mcp23s17_state
-        .borrow()
+        .lock()
         .spi_control_byte(SpiCommand::Write);
     assert_eq!(0x42, spi_ctrl, "Unexpected control byte: 0x{spi_ctrl:02x}");
 }
@@ -1129,3 +1169,2889 @@ fn display_spi_bus() {
     let s = format!("{}", SpiBus::Spi3);
     assert_eq!(s, "Spi3");
 }
+
+#[test]
+fn with_transport() {
+    let mcp23s17 = Mcp23s17::with_transport(
+        HardwareAddress::new(0).unwrap(),
+        MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0),
+    );
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0x55);
+
+    assert_eq!(mcp23s17.get_spi_bus(), None);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPIOB).expect("Bad read"),
+        0x55
+    );
+}
+
+#[test]
+fn new_mock_spi_bus() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi3,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    assert_eq!(mcp23s17.get_spi_bus(), Some(SpiBus::Spi3));
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn with_spi_device() {
+    let mock_spi = MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0);
+    mock_spi.set_mock_data(RegisterAddress::GPIOA, 0x5a);
+    let mcp23s17 = Mcp23s17::with_spi_device(HardwareAddress::new(0).unwrap(), mock_spi);
+
+    mcp23s17
+        .set_bits(RegisterAddress::GPIOA, 0xaa)
+        .expect("Bad set bits");
+
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPIOA).expect("Bad read"),
+        0xfa
+    );
+    assert_eq!(mcp23s17.get_spi_bus(), None);
+}
+
+#[test]
+fn read_output_pin_latch_gpiob() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+    mcp23s17.set_mock_data(RegisterAddress::OLATB, 0b0000_0100);
+
+    let pin = mcp23s17
+        .get(Port::GpioB, 2)
+        .expect("Failed to get pin")
+        .into_output_pin()
+        .expect("Failed to convert to OutputPin");
+    assert_eq!(pin.read_latch().expect("Bad latch read"), Level::High);
+    assert_eq!(pin.read().expect("Bad pin read"), Level::Low);
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn embedded_hal_input_pin() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0b0000_1000);
+
+    let mut pin = mcp23s17
+        .get(Port::GpioA, 3)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    assert!(embedded_hal::digital::InputPin::is_high(&mut pin).expect("Bad read"));
+    assert!(!embedded_hal::digital::InputPin::is_low(&mut pin).expect("Bad read"));
+}
+
+#[cfg(feature = "embedded-hal")]
+#[test]
+fn embedded_hal_stateful_output_pin() {
+    use embedded_hal::digital::StatefulOutputPin;
+
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::OLATA, 0b0000_0000);
+
+    let mut pin = mcp23s17
+        .get(Port::GpioA, 6)
+        .expect("Failed to get pin")
+        .into_output_pin()
+        .expect("Failed to convert to OutputPin");
+    assert!(pin.is_set_low().expect("Bad latch read"));
+
+    // Emulate the device reflecting the GPIO write in the output latch.
+    mcp23s17.set_mock_data(RegisterAddress::OLATA, 0b0100_0000);
+    StatefulOutputPin::toggle(&mut pin).expect("Bad toggle");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::GPIOA),
+        (0b0000_0000, 1, 1),
+        "Bad GPIOA"
+    );
+}
+
+/// Drive a future to completion on the current thread.
+///
+/// The mocks never leave a future pending, so there is no need for a real waker.
+#[cfg(feature = "embedded-hal-async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// A host GPIO connected to the MCP23S17 `INTA` output that is always asserted.
+#[cfg(feature = "embedded-hal-async")]
+struct AssertedInterruptLine;
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal::digital::ErrorType for AssertedInterruptLine {
+    type Error = std::convert::Infallible;
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal_async::digital::Wait for AssertedInterruptLine {
+    async fn wait_for_high(&mut self) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> result::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// An async SPI device over a mock that stays inspectable by the test, whose transfers
+/// fail once `failing` is set.
+#[cfg(feature = "embedded-hal-async")]
+struct SharedSpiDevice {
+    mock_spi: std::rc::Rc<MockSpi>,
+    failing: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl SharedSpiDevice {
+    /// Create the device along with the mock behind it and the switch that makes its
+    /// transfers fail.
+    fn new() -> (Self, std::rc::Rc<MockSpi>, Arc<AtomicBool>) {
+        let mock_spi = std::rc::Rc::new(MockSpi::new(
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        ));
+        let failing = Arc::new(AtomicBool::new(false));
+        let spi = SharedSpiDevice {
+            mock_spi: mock_spi.clone(),
+            failing: failing.clone(),
+        };
+        (spi, mock_spi, failing)
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal::spi::ErrorType for SharedSpiDevice {
+    type Error = embedded_hal::spi::ErrorKind;
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal_async::spi::SpiDevice for SharedSpiDevice {
+    async fn transaction(
+        &mut self,
+        operations: &mut [embedded_hal::spi::Operation<'_, u8>],
+    ) -> result::Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                embedded_hal::spi::Operation::Transfer(read_buffer, write_buffer)
+                    if !self.failing.load(Ordering::Relaxed) =>
+                {
+                    self.mock_spi
+                        .transfer(read_buffer, write_buffer)
+                        .map_err(|_| embedded_hal::spi::ErrorKind::Other)?;
+                }
+                _ => return Err(embedded_hal::spi::ErrorKind::Other),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A host GPIO connected to the MCP23S17 `INTA` output that records the level awaited,
+/// then fails and makes the SPI device fail too.
+#[cfg(feature = "embedded-hal-async")]
+struct FailingInterruptLine {
+    failing: Arc<AtomicBool>,
+    awaited: std::rc::Rc<std::cell::Cell<Option<Level>>>,
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl FailingInterruptLine {
+    /// Await the `level`, failing.
+    fn fail(&self, level: Level) -> result::Result<(), embedded_hal::digital::ErrorKind> {
+        self.awaited.set(Some(level));
+        self.failing.store(true, Ordering::Relaxed);
+        Err(embedded_hal::digital::ErrorKind::Other)
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal::digital::ErrorType for FailingInterruptLine {
+    type Error = embedded_hal::digital::ErrorKind;
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal_async::digital::Wait for FailingInterruptLine {
+    async fn wait_for_high(&mut self) -> result::Result<(), Self::Error> {
+        self.fail(Level::High)
+    }
+
+    async fn wait_for_low(&mut self) -> result::Result<(), Self::Error> {
+        self.fail(Level::Low)
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> result::Result<(), Self::Error> {
+        self.fail(Level::High)
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> result::Result<(), Self::Error> {
+        self.fail(Level::Low)
+    }
+
+    async fn wait_for_any_edge(&mut self) -> result::Result<(), Self::Error> {
+        self.fail(Level::Low)
+    }
+}
+
+/// A host GPIO connected to the MCP23S17 `INTA` output that is never asserted.
+#[cfg(feature = "embedded-hal-async")]
+struct IdleInterruptLine;
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal::digital::ErrorType for IdleInterruptLine {
+    type Error = std::convert::Infallible;
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl embedded_hal_async::digital::Wait for IdleInterruptLine {
+    async fn wait_for_high(&mut self) -> result::Result<(), Self::Error> {
+        std::future::pending().await
+    }
+
+    async fn wait_for_low(&mut self) -> result::Result<(), Self::Error> {
+        std::future::pending().await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> result::Result<(), Self::Error> {
+        std::future::pending().await
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> result::Result<(), Self::Error> {
+        std::future::pending().await
+    }
+
+    async fn wait_for_any_edge(&mut self) -> result::Result<(), Self::Error> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_new_preserves_iocon() {
+    let (spi, mock_spi, _) = SharedSpiDevice::new();
+    mock_spi.set_mock_data(RegisterAddress::IOCON, 0b0010_1010);
+
+    let mcp23s17 = block_on(asynch::Mcp23s17::new(
+        HardwareAddress::new(0).unwrap(),
+        spi,
+        AssertedInterruptLine,
+    ))
+    .expect("Create MCP23S17");
+    assert_eq!(block_on(mcp23s17.bank_mode()), BankMode::Interleaved);
+
+    // IOCON is only read.
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::IOCON),
+        (0b0010_1010, 1, 0)
+    );
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_banked_layout() {
+    let (spi, mock_spi, _) = SharedSpiDevice::new();
+    mock_spi.set_mock_data(RegisterAddress::IOCON, (IOCON::BANK | IOCON::MIRROR).bits());
+
+    block_on(async {
+        let mcp23s17 =
+            asynch::Mcp23s17::new(HardwareAddress::new(0).unwrap(), spi, AssertedInterruptLine)
+                .await
+                .expect("Create MCP23S17");
+        mcp23s17
+            .assume_bank_mode(BankMode::Segregated)
+            .await
+            .expect("Failed to assume bank mode");
+        assert_eq!(mcp23s17.bank_mode().await, BankMode::Segregated);
+
+        let pin = mcp23s17
+            .get(Port::GpioB, 1)
+            .expect("Failed to get pin")
+            .into_output_pin()
+            .await
+            .expect("Failed to convert to OutputPin");
+        pin.set_high().await.expect("Bad write");
+
+        mcp23s17
+            .set_bank_mode(BankMode::Interleaved)
+            .await
+            .expect("Failed to set bank mode");
+        assert_eq!(mcp23s17.bank_mode().await, BankMode::Interleaved);
+    });
+
+    // The registers were found at their banked addresses.
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::IODIRB),
+        (0b1111_1101, 1, 1)
+    );
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::GPIOB),
+        (0b0000_0010, 1, 1)
+    );
+    assert_eq!(mock_spi.get_mock_data(RegisterAddress::IPOLA).2, 0);
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::IOCON).0,
+        IOCON::MIRROR.bits()
+    );
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_cache() {
+    let (spi, mock_spi, _) = SharedSpiDevice::new();
+
+    block_on(async {
+        let mcp23s17 =
+            asynch::Mcp23s17::new(HardwareAddress::new(0).unwrap(), spi, AssertedInterruptLine)
+                .await
+                .expect("Create MCP23S17");
+        mcp23s17
+            .enable_cache()
+            .await
+            .expect("Failed to enable cache");
+        let pin = mcp23s17
+            .get(Port::GpioA, 4)
+            .expect("Failed to get pin")
+            .into_output_pin()
+            .await
+            .expect("Failed to convert to OutputPin");
+        pin.set_high().await.expect("Bad write");
+        assert_eq!(
+            mcp23s17.read(RegisterAddress::OLATA).await.unwrap(),
+            0b0001_0000
+        );
+    });
+
+    // The read-modify-writes were based on the cache, including the output latch.
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::IODIRA),
+        (0b1110_1111, 1, 1)
+    );
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::GPIOA),
+        (0b0001_0000, 0, 1)
+    );
+    assert_eq!(mock_spi.get_mock_data(RegisterAddress::OLATA).1, 1);
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_wait_keeps_original_error() {
+    use embedded_hal_async::digital::Wait;
+
+    for (iocon, awaited) in [
+        (IOCON::empty(), Level::Low),
+        (IOCON::INTPOL, Level::High),
+        (IOCON::INTPOL | IOCON::ODR, Level::Low),
+    ] {
+        let (spi, mock_spi, failing) = SharedSpiDevice::new();
+        mock_spi.set_mock_data(RegisterAddress::IOCON, iocon.bits());
+        let interrupt_line = FailingInterruptLine {
+            failing,
+            awaited: std::rc::Rc::default(),
+        };
+        let awaited_level = interrupt_line.awaited.clone();
+
+        block_on(async {
+            let mcp23s17 =
+                asynch::Mcp23s17::new(HardwareAddress::new(0).unwrap(), spi, interrupt_line)
+                    .await
+                    .expect("Create MCP23S17");
+            let mut pin = mcp23s17
+                .get(Port::GpioA, 2)
+                .expect("Failed to get pin")
+                .into_input_pin()
+                .await
+                .expect("Failed to convert to InputPin");
+
+            // Restoring the interrupt mode fails too, but the wait's error is returned.
+            assert!(matches!(
+                pin.wait_for_falling_edge().await,
+                Err(Mcp23s17Error::InterruptLineError(
+                    embedded_hal::digital::ErrorKind::Other
+                ))
+            ));
+        });
+        assert_eq!(awaited_level.get(), Some(awaited), "IOCON={iocon:?}");
+        assert_eq!(
+            mock_spi.get_mock_data(RegisterAddress::GPINTENA).0,
+            0b0000_0100
+        );
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_wait_for_high_already_high() {
+    use embedded_hal_async::digital::Wait;
+
+    let mock_spi = MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0);
+    mock_spi.set_mock_data(RegisterAddress::GPIOB, 0b0000_0100);
+
+    block_on(async {
+        let mcp23s17 = asynch::Mcp23s17::new(
+            HardwareAddress::new(0).unwrap(),
+            mock_spi,
+            AssertedInterruptLine,
+        )
+        .await
+        .expect("Create MCP23S17");
+        let mut pin = mcp23s17
+            .get(Port::GpioB, 2)
+            .expect("Failed to get pin")
+            .into_input_pin()
+            .await
+            .expect("Failed to convert to InputPin");
+
+        pin.wait_for_high().await.expect("Bad wait");
+
+        // No need to resort to interrupts.
+        assert_eq!(
+            mcp23s17.read(RegisterAddress::GPINTENB).await.unwrap(),
+            0b0000_0000
+        );
+    });
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_wait_for_rising_edge() {
+    use embedded_hal_async::digital::Wait;
+
+    let mock_spi = MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0);
+    mock_spi.set_mock_data(RegisterAddress::GPINTENA, 0b0000_0001);
+    mock_spi.set_mock_data(RegisterAddress::INTCONA, 0b1111_1111);
+    mock_spi.set_mock_data(RegisterAddress::INTFA, 0b0000_1000);
+    mock_spi.set_mock_data(RegisterAddress::INTCAPA, 0b0000_1000);
+
+    block_on(async {
+        let mcp23s17 = asynch::Mcp23s17::new(
+            HardwareAddress::new(0).unwrap(),
+            mock_spi,
+            AssertedInterruptLine,
+        )
+        .await
+        .expect("Create MCP23S17");
+        let mut pin = mcp23s17
+            .get(Port::GpioA, 3)
+            .expect("Failed to get pin")
+            .into_pullup_input_pin()
+            .await
+            .expect("Failed to convert to InputPin");
+
+        pin.wait_for_rising_edge().await.expect("Bad wait");
+
+        // The pin was set to interrupt on both edges, then interrupts were disabled
+        // again without disturbing the other pins.
+        assert_eq!(
+            mcp23s17.read(RegisterAddress::INTCONA).await.unwrap(),
+            0b1111_0111
+        );
+        assert_eq!(
+            mcp23s17.read(RegisterAddress::GPINTENA).await.unwrap(),
+            0b0000_0001
+        );
+    });
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_cancelled_wait_restores_interrupt_mode() {
+    use embedded_hal_async::digital::Wait;
+    use std::future::Future;
+
+    let (spi, mock_spi, _) = SharedSpiDevice::new();
+
+    block_on(async {
+        let mcp23s17 =
+            asynch::Mcp23s17::new(HardwareAddress::new(0).unwrap(), spi, IdleInterruptLine)
+                .await
+                .expect("Create MCP23S17");
+        let mut pin = mcp23s17
+            .get(Port::GpioA, 2)
+            .expect("Failed to get pin")
+            .into_input_pin()
+            .await
+            .expect("Failed to convert to InputPin");
+
+        // Give up on the wait whilst the interrupt line is being awaited.
+        {
+            let mut wait = std::pin::pin!(pin.wait_for_falling_edge());
+            let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+            assert!(wait.as_mut().poll(&mut context).is_pending());
+        }
+        assert_eq!(
+            mock_spi.get_mock_data(RegisterAddress::GPINTENA).0,
+            0b0000_0100
+        );
+
+        // The next wait restores the original interrupt mode first.
+        mock_spi.set_mock_data(RegisterAddress::GPIOA, 0b0000_0100);
+        pin.wait_for_high().await.expect("Bad wait");
+    });
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::GPINTENA).0,
+        0b0000_0000
+    );
+}
+
+#[cfg(feature = "embedded-hal-async")]
+#[test]
+fn asynch_duplicate_pin() {
+    let mcp23s17 = block_on(asynch::Mcp23s17::new(
+        HardwareAddress::new(0).unwrap(),
+        MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0),
+        AssertedInterruptLine,
+    ))
+    .expect("Create MCP23S17");
+
+    let pin = mcp23s17.get(Port::GpioB, 7).expect("Failed to get pin");
+    assert!(matches!(
+        mcp23s17.get(Port::GpioB, 7),
+        Err(Mcp23s17Error::PinNotAvailable(7))
+    ));
+    drop(pin);
+    let _pin = mcp23s17
+        .get(Port::GpioB, 7)
+        .expect("Failed to get pin again");
+}
+
+#[test]
+fn mcp23017_write_output_pin() {
+    let mcp23017 = Mcp23017::new_mock_i2c(HardwareAddress::new(5).unwrap());
+    mcp23017.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+
+    let pin = mcp23017
+        .get(Port::GpioB, 1)
+        .expect("Failed to get pin")
+        .into_output_pin_high()
+        .expect("Failed to convert to OutputPin");
+    assert_eq!(pin.read().expect("Bad pin read"), Level::High);
+
+    assert_eq!(
+        mcp23017.get_mock_data(RegisterAddress::IODIRB),
+        (0b1111_1101, 1, 1),
+        "Bad IODIRB"
+    );
+    assert_eq!(
+        mcp23017.get_mock_data(RegisterAddress::GPIOB),
+        (0b0000_0010, 2, 1),
+        "Bad GPIOB"
+    );
+    assert_eq!(mcp23017.get_spi_bus(), None);
+}
+
+#[test]
+fn mcp23017_wrong_address() {
+    let mcp23017 = Mcp23017::with_i2c(
+        HardwareAddress::new(1).unwrap(),
+        MockI2c::new(HardwareAddress::new(2).unwrap()),
+    );
+
+    let result = mcp23017.read(RegisterAddress::GPIOA);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::I2cError { .. })),
+        "Unexpected result: {result:?}"
+    );
+}
+
+#[test]
+fn mcp23s08_register_layout() {
+    assert_eq!(
+        RegisterLayout::SinglePort
+            .address(RegisterAddress::IODIRA)
+            .unwrap(),
+        0x00
+    );
+    assert_eq!(
+        RegisterLayout::SinglePort
+            .address(RegisterAddress::IOCON)
+            .unwrap(),
+        0x05
+    );
+    assert_eq!(
+        RegisterLayout::SinglePort
+            .address(RegisterAddress::OLATA)
+            .unwrap(),
+        0x0a
+    );
+    assert!(matches!(
+        RegisterLayout::SinglePort.address(RegisterAddress::GPIOB),
+        Err(Mcp23s17Error::RegisterAddressBoundsError)
+    ));
+}
+
+#[test]
+fn mcp23s08_write_output_pin() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(3).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08.set_mock_data(RegisterAddress::GPIOA, 0b0000_0000);
+
+    let pin = mcp23s08
+        .get(6)
+        .expect("Failed to get pin")
+        .into_output_pin_high()
+        .expect("Failed to convert to OutputPin");
+    assert_eq!(pin.read().expect("Bad pin read"), Level::High);
+
+    assert_eq!(
+        mcp23s08.get_mock_data(RegisterAddress::IODIRA),
+        (0b1011_1111, 1, 1),
+        "Bad IODIR"
+    );
+    assert_eq!(
+        mcp23s08.get_mock_data(RegisterAddress::IPOLA),
+        (0x00, 0, 0),
+        "Bad IPOL"
+    );
+    assert_eq!(
+        mcp23s08.get_mock_data(RegisterAddress::GPIOA),
+        (0b0100_0000, 2, 1),
+        "Bad GPIO"
+    );
+}
+
+#[test]
+fn mcp23s08_port_b_register() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+
+    let result = mcp23s08.read(RegisterAddress::GPIOB);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::RegisterAddressBoundsError)),
+        "Unexpected result: {result:?}"
+    );
+}
+
+#[test]
+fn mcp23s08_bad_hardware_address() {
+    let result = Mcp23s08::new_mock(
+        HardwareAddress::new(4).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    );
+    assert!(
+        matches!(result, Err(Mcp23s17Error::HardwareAddressBoundsError(4))),
+        "Unexpected result: {result:?}"
+    );
+}
+
+#[test]
+fn cached_output_pin_write() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::OLATB, 0b1000_0000);
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+
+    let pin = mcp23s17
+        .get(Port::GpioB, 2)
+        .expect("Failed to get pin")
+        .into_output_pin()
+        .expect("Failed to convert to OutputPin");
+    pin.set_high().expect("Bad pin write");
+    pin.set_low().expect("Bad pin write");
+    pin.set_high().expect("Bad pin write");
+
+    // Only the resync has read the registers.
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRB),
+        (0b1111_1011, 1, 1),
+        "Bad IODIRB"
+    );
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::GPIOB),
+        (0b1000_0100, 0, 3),
+        "Bad GPIOB"
+    );
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::OLATB).expect("Bad read"),
+        0b1000_0100
+    );
+}
+
+#[test]
+fn cache_resync() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    mcp23s17
+        .write(RegisterAddress::IOCON, 0b0010_0000)
+        .expect("Bad write");
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IOCON2).expect("Bad read"),
+        0b0010_0000
+    );
+
+    // Something else changes the device behind the cache's back.
+    mcp23s17.set_mock_data(RegisterAddress::GPPUA, 0x0f);
+    assert_eq!(mcp23s17.read(RegisterAddress::GPPUA).expect("Bad read"), 0);
+    mcp23s17.resync().expect("Failed to resync");
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPPUA).expect("Bad read"),
+        0x0f
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPPUA), (0x0f, 2, 0));
+
+    // Volatile registers are always read from the device.
+    mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0x55);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPIOA).expect("Bad read"),
+        0x55
+    );
+
+    mcp23s17.disable_cache();
+    mcp23s17.read(RegisterAddress::GPPUA).expect("Bad read");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPPUA), (0x0f, 3, 0));
+}
+
+#[test]
+fn read_registers() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0x01);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0x02);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0x03);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0x04);
+
+    let mut data = [0u8; 4];
+    mcp23s17
+        .read_registers(RegisterAddress::INTFA, &mut data)
+        .expect("Bad burst read");
+    assert_eq!(data, [0x01, 0x02, 0x03, 0x04]);
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::INTCAPB),
+        (0x04, 1, 0)
+    );
+
+    let mut data = [0u8; RegisterAddress::LENGTH];
+    mcp23s17
+        .read_registers(RegisterAddress::IODIRA, &mut data)
+        .expect("Bad full register dump");
+    assert_eq!(data[..2], [0xff, 0xff]);
+
+    let result = mcp23s17.read_registers(RegisterAddress::OLATA, &mut [0u8; 3]);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::RegisterAddressBoundsError)),
+        "Unexpected result: {result:?}"
+    );
+}
+
+#[test]
+fn write_registers() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+
+    mcp23s17
+        .write_registers(RegisterAddress::GPIOA, &[0x12, 0x34])
+        .expect("Bad burst write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOA), (0x12, 0, 1));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOB), (0x34, 0, 1));
+    // The cache follows the burst write to the output latches.
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::OLATB).expect("Bad read"),
+        0x34
+    );
+}
+
+#[test]
+fn burst_rejected_in_byte_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    mcp23s17
+        .write(RegisterAddress::IOCON, IOCON::SEQOP_OFF.bits())
+        .expect("Bad write");
+
+    let result = mcp23s17.write_registers(RegisterAddress::IODIRA, &[0x01, 0x02, 0x03, 0x04]);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::UnsupportedConfiguration(_))),
+        "Unexpected result: {result:?}"
+    );
+    let result = mcp23s17.read_registers(RegisterAddress::IODIRA, &mut [0u8; 4]);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::UnsupportedConfiguration(_))),
+        "Unexpected result: {result:?}"
+    );
+    // A pair can't be accessed together from its `B` register either.
+    let result = mcp23s17.write_registers(RegisterAddress::IODIRB, &[0x01, 0x02]);
+    assert!(
+        matches!(result, Err(Mcp23s17Error::UnsupportedConfiguration(_))),
+        "Unexpected result: {result:?}"
+    );
+    // Neither the device nor the cache was touched.
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRA),
+        (0xff, 1, 0)
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IPOLA), (0x00, 1, 0));
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IODIRA).expect("Bad read"),
+        0xff
+    );
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IPOLA).expect("Bad read"),
+        0x00
+    );
+
+    // The two registers of a pair can still be written together.
+    mcp23s17
+        .write_registers(RegisterAddress::IODIRA, &[0x01, 0x02])
+        .expect("Bad pair write");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRA),
+        (0x01, 1, 1)
+    );
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRB),
+        (0x02, 1, 1)
+    );
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IODIRB).expect("Bad read"),
+        0x02
+    );
+}
+
+#[test]
+fn mock_byte_mode_toggles_register_pair() {
+    let mock_spi = MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0);
+    mock_spi.set_mock_data(RegisterAddress::IOCON, IOCON::SEQOP_OFF.bits());
+    mock_spi
+        .transfer(&mut [0u8; 5], &[0x40, 0x12, 0x01, 0x02, 0x03])
+        .expect("Bad transfer");
+    assert_eq!(mock_spi.get_mock_data(RegisterAddress::GPIOA), (0x03, 0, 2));
+    assert_eq!(mock_spi.get_mock_data(RegisterAddress::GPIOB), (0x02, 0, 1));
+}
+
+#[test]
+fn mock_sequential_mode_wraps() {
+    let mock_spi = MockSpi::new(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0);
+    mock_spi
+        .transfer(&mut [0u8; 4], &[0x40, 0x15, 0xaa, 0x55])
+        .expect("Bad transfer");
+    assert_eq!(mock_spi.get_mock_data(RegisterAddress::OLATB), (0xaa, 0, 1));
+    assert_eq!(
+        mock_spi.get_mock_data(RegisterAddress::IODIRA),
+        (0x55, 0, 1)
+    );
+}
+
+#[test]
+fn read_gpio16() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0x01);
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0x80);
+
+    assert_eq!(mcp23s17.read_gpio16().expect("Bad read"), 0x8001);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOA), (0x01, 1, 0));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOB), (0x80, 1, 0));
+}
+
+#[test]
+fn write_olat16() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    mcp23s17.write_olat16(0x1234).expect("Bad write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATA), (0x34, 0, 1));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB), (0x12, 0, 1));
+}
+
+#[test]
+fn modify_olat16() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::OLATA, 0b1111_0000);
+    mcp23s17.set_mock_data(RegisterAddress::OLATB, 0b0000_1111);
+
+    mcp23s17
+        .modify_olat16(0b0000_0001_1000_0001, 0b0000_0001_0000_0001)
+        .expect("Bad modify");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATA),
+        (0b0111_0001, 1, 1)
+    );
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATB),
+        (0b0000_1111, 1, 1)
+    );
+
+    // With the cache enabled the read is not needed.
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    mcp23s17.modify_olat16(0xff00, 0xa500).expect("Bad modify");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATA),
+        (0b0111_0001, 2, 2)
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB), (0xa5, 2, 2));
+}
+
+#[test]
+fn gpio16_byte_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    for iocon in [IOCON::SEQOP_OFF, IOCON::SEQOP_OFF | IOCON::BANK_ON] {
+        mcp23s17
+            .write(RegisterAddress::IOCON, iocon.bits())
+            .expect("Bad write");
+        mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0x34);
+        mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0x12);
+        mcp23s17.set_mock_data(RegisterAddress::OLATA, 0xf0);
+        mcp23s17.set_mock_data(RegisterAddress::OLATB, 0x0f);
+        let (_, olata_reads, olata_writes) = mcp23s17.get_mock_data(RegisterAddress::OLATA);
+
+        assert_eq!(mcp23s17.read_gpio16().expect("Bad read"), 0x1234);
+        mcp23s17.modify_olat16(0x8181, 0x8001).expect("Bad modify");
+        assert_eq!(
+            mcp23s17.get_mock_data(RegisterAddress::OLATA),
+            (0x71, olata_reads + 1, olata_writes + 1)
+        );
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB).0, 0x8e);
+        mcp23s17.write_olat16(0xbeef).expect("Bad write");
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATA).0, 0xef);
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB).0, 0xbe);
+        // Nothing spilled into the neighbouring registers.
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IODIRA).2, 0);
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IODIRB).2, 0);
+    }
+}
+
+#[test]
+fn port_bus_read_write() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    let port_bus = mcp23s17
+        .get_port_bus(Port::GpioB, 0x0f, 0x03)
+        .expect("Failed to get PortBus");
+    assert_eq!(port_bus.get_port(), Port::GpioB);
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRB),
+        (0x0f, 0, 1)
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPPUB), (0x03, 0, 1));
+
+    port_bus.write(0xa0).expect("Bad write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOB), (0xa0, 0, 1));
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0xa5);
+    assert_eq!(port_bus.read().expect("Bad read"), 0xa5);
+
+    port_bus.set_direction(0xff).expect("Bad direction");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IODIRB),
+        (0xff, 0, 2)
+    );
+}
+
+#[test]
+fn port_bus_claims_pins() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    let pin = mcp23s17.get(Port::GpioB, 3).expect("Failed to get pin");
+    assert!(matches!(
+        mcp23s17.get_port_bus(Port::GpioB, 0xff, 0x00),
+        Err(Mcp23s17Error::PinNotAvailable(3))
+    ));
+    drop(pin);
+
+    let port_bus = mcp23s17
+        .get_port_bus(Port::GpioB, 0xff, 0x00)
+        .expect("Failed to get PortBus");
+    assert!(matches!(
+        mcp23s17.get(Port::GpioB, 0),
+        Err(Mcp23s17Error::PinNotAvailable(0))
+    ));
+    let _pin = mcp23s17
+        .get(Port::GpioA, 0)
+        .expect("Other port should be available");
+    drop(port_bus);
+
+    let _pin = mcp23s17
+        .get(Port::GpioB, 0)
+        .expect("Failed to get pin after PortBus dropped");
+}
+
+#[test]
+fn pin_group_write_bits() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::OLATA, 0b0101_0011);
+    mcp23s17.set_mock_data(RegisterAddress::OLATB, 0b1111_1111);
+
+    let pins = [
+        (Port::GpioA, 2),
+        (Port::GpioA, 3),
+        (Port::GpioB, 5),
+        (Port::GpioA, 7),
+    ]
+    .into_iter()
+    .map(|(port, pin)| mcp23s17.get(port, pin)?.into_output_pin())
+    .collect::<Result<Vec<_>>>()
+    .expect("Failed to get OutputPins");
+    let group = PinGroup::new(pins).expect("Failed to create PinGroup");
+
+    // Both output latches are read and then written in a burst.
+    group.write_bits(0b1001).expect("Bad write");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATA),
+        (0b1101_0111, 1, 1),
+        "Bad OLATA"
+    );
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATB),
+        (0b1101_1111, 1, 1),
+        "Bad OLATB"
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOA).2, 0);
+
+    let pins = group.into_pins();
+    assert_eq!(pins.len(), 4);
+}
+
+#[test]
+fn pin_group_single_port_write() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let pins = vec![
+        mcp23s17
+            .get(Port::GpioB, 0)
+            .expect("Failed to get pin")
+            .into_output_pin()
+            .expect("Failed to convert to OutputPin"),
+    ];
+    let group = PinGroup::new(pins).expect("Failed to create PinGroup");
+
+    group.write_bits(0x01).expect("Bad write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATA), (0x00, 0, 0));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB), (0x01, 1, 1));
+}
+
+#[test]
+fn pin_group_invalid() {
+    assert!(matches!(
+        PinGroup::<MockSpi>::new(Vec::new()),
+        Err(Mcp23s17Error::InvalidPinGroup)
+    ));
+
+    let mcp23s17_1 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let mcp23s17_2 = Mcp23s17::new_mock(
+        HardwareAddress::new(1).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let pins = vec![
+        mcp23s17_1
+            .get(Port::GpioA, 0)
+            .expect("Failed to get pin")
+            .into_output_pin()
+            .expect("Failed to convert to OutputPin"),
+        mcp23s17_2
+            .get(Port::GpioA, 1)
+            .expect("Failed to get pin")
+            .into_output_pin()
+            .expect("Failed to convert to OutputPin"),
+    ];
+    assert!(matches!(
+        PinGroup::new(pins),
+        Err(Mcp23s17Error::InvalidPinGroup)
+    ));
+}
+
+#[test]
+fn inverted_pullup_input_pin() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0b0001_0000);
+
+    let mut pin = mcp23s17
+        .get(Port::GpioB, 4)
+        .expect("Failed to get pin")
+        .into_inverted_pullup_input_pin()
+        .expect("Failed to convert to InputPin");
+    assert!(pin.is_inverted());
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IPOLB), (0x10, 1, 1));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPPUB), (0x10, 1, 1));
+    assert_eq!(pin.read().expect("Bad read"), Level::Low);
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0b0000_0000);
+    assert!(pin.is_high().expect("Bad read"));
+
+    pin.set_inverted(false).expect("Failed to clear inversion");
+    assert!(!pin.is_inverted());
+    assert!(pin.is_low().expect("Bad read"));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IPOLB), (0x00, 2, 2));
+
+    pin.set_inverted(true).expect("Failed to set inversion");
+    drop(pin);
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IPOLB),
+        (0x00, 4, 4),
+        "IPOL not restored on drop"
+    );
+}
+
+#[test]
+fn inverted_input_pin_interrupt_capture() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0000);
+
+    let _pin = mcp23s17
+        .get(Port::GpioA, 1)
+        .expect("Failed to get pin")
+        .into_inverted_input_pin()
+        .expect("Failed to convert to InputPin");
+    assert_eq!(
+        mcp23s17
+            .get_bit(RegisterAddress::INTCAPA, 1)
+            .expect("Bad read"),
+        Level::High
+    );
+    assert_eq!(
+        mcp23s17
+            .get_bit(RegisterAddress::INTCAPA, 0)
+            .expect("Bad read"),
+        Level::Low
+    );
+}
+
+#[test]
+fn take_interrupts() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0100);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0b1111_1011);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b1000_0001);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0b1000_0000);
+
+    let events = mcp23s17.take_interrupts().expect("Bad take interrupts");
+    assert_eq!(
+        events,
+        vec![
+            InterruptEvent {
+                port: Port::GpioA,
+                pin: 2,
+                level: Level::Low
+            },
+            InterruptEvent {
+                port: Port::GpioB,
+                pin: 0,
+                level: Level::Low
+            },
+            InterruptEvent {
+                port: Port::GpioB,
+                pin: 7,
+                level: Level::High
+            },
+        ]
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTCAPB).1, 1);
+
+    // Reading INTCAP cleared the interrupt condition.
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTFA).0, 0);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTFB).0, 0);
+    assert!(
+        mcp23s17
+            .take_interrupts()
+            .expect("Bad take interrupts")
+            .is_empty()
+    );
+}
+
+#[test]
+fn take_interrupts_byte_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    for iocon in [IOCON::SEQOP_OFF, IOCON::SEQOP_OFF | IOCON::BANK_ON] {
+        mcp23s17
+            .write(RegisterAddress::IOCON, iocon.bits())
+            .expect("Bad write");
+        mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0100);
+        mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0001_0000);
+        mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0100);
+        mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0b1110_1111);
+
+        let events = mcp23s17.take_interrupts().expect("Bad take interrupts");
+        assert_eq!(
+            events,
+            vec![
+                InterruptEvent {
+                    port: Port::GpioA,
+                    pin: 2,
+                    level: Level::High
+                },
+                InterruptEvent {
+                    port: Port::GpioB,
+                    pin: 4,
+                    level: Level::Low
+                },
+            ],
+            "IOCON={iocon:?}"
+        );
+        // Reading INTCAP cleared the interrupts.
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTFA).0, 0);
+        assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTFB).0, 0);
+    }
+
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08
+        .write(RegisterAddress::IOCON, IOCON::SEQOP_OFF.bits())
+        .expect("Bad write");
+    mcp23s08.set_mock_data(RegisterAddress::INTFA, 0b0000_0001);
+    mcp23s08.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0001);
+    assert_eq!(
+        mcp23s08.take_interrupts().expect("Bad take interrupts"),
+        vec![InterruptEvent {
+            port: Port::GpioA,
+            pin: 0,
+            level: Level::High
+        }]
+    );
+    assert_eq!(mcp23s08.get_mock_data(RegisterAddress::INTFA).0, 0);
+}
+
+#[test]
+fn mcp23s08_take_interrupts() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08.set_mock_data(RegisterAddress::INTFA, 0b0010_0000);
+    mcp23s08.set_mock_data(RegisterAddress::INTCAPA, 0b0010_0000);
+
+    let events = mcp23s08.take_interrupts().expect("Bad take interrupts");
+    assert_eq!(
+        events,
+        vec![InterruptEvent {
+            port: Port::GpioA,
+            pin: 5,
+            level: Level::High
+        }]
+    );
+}
+
+#[test]
+fn mcp23s08_service_interrupts() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let mut pin = mcp23s08
+        .get(2)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    let callback_levels = levels.clone();
+    pin.set_interrupt_callback(InterruptMode::BothEdges, move |level| {
+        callback_levels.lock().unwrap().push(level)
+    })
+    .expect("Failed to set callback");
+
+    mcp23s08.set_mock_data(RegisterAddress::INTFA, 0b0000_0100);
+    mcp23s08.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0100);
+    let events = mcp23s08.service_interrupts().expect("Bad service");
+    assert_eq!(
+        events,
+        vec![InterruptEvent {
+            port: Port::GpioA,
+            pin: 2,
+            level: Level::High
+        }]
+    );
+    assert_eq!(*levels.lock().unwrap(), vec![Level::High]);
+}
+
+#[test]
+fn service_interrupts_dispatches_callbacks() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let mut pin = mcp23s17
+        .get(Port::GpioB, 3)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    let callback_levels = levels.clone();
+    pin.set_interrupt_callback(InterruptMode::BothEdges, move |level| {
+        callback_levels.lock().unwrap().push(level)
+    })
+    .expect("Failed to set callback");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::GPINTENB).0,
+        0b0000_1000
+    );
+
+    // An interrupt on another pin without a callback is still reported.
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0001);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0b0000_1000);
+    let events = mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(events.len(), 2);
+    assert_eq!(*levels.lock().unwrap(), vec![Level::High]);
+
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0000);
+    mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(*levels.lock().unwrap(), vec![Level::High, Level::Low]);
+
+    pin.clear_interrupt_callback()
+        .expect("Failed to clear callback");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPINTENB).0, 0);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_1000);
+    mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(levels.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn interrupt_callback_not_registered_on_error() {
+    let (transport, failing) = FailingTransport::new();
+    let mcp23s17 = Mcp23s17::with_transport(HardwareAddress::new(0).unwrap(), transport);
+    let calls = Arc::new(Mutex::new(0));
+    let mut pin = mcp23s17
+        .get(Port::GpioA, 1)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+
+    failing.store(true, Ordering::Relaxed);
+    let callback_calls = calls.clone();
+    assert!(matches!(
+        pin.set_interrupt_callback(InterruptMode::BothEdges, move |_| {
+            *callback_calls.lock().unwrap() += 1
+        }),
+        Err(Mcp23s17Error::TransportError { .. })
+    ));
+
+    failing.store(false, Ordering::Relaxed);
+    mcp23s17
+        .write(RegisterAddress::INTFA, 0b0000_0010)
+        .expect("Bad write");
+    assert_eq!(mcp23s17.service_interrupts().expect("Bad service").len(), 1);
+    assert_eq!(*calls.lock().unwrap(), 0);
+}
+
+// The pin is shared through an `Arc` so that the callback is `Send` with the `sync`
+// feature.
+#[test]
+#[cfg_attr(not(feature = "sync"), allow(clippy::arc_with_non_send_sync))]
+fn interrupt_callback_released_in_callback() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    let calls = Arc::new(Mutex::new(0));
+    let pin = Arc::new(Mutex::new(Some(
+        mcp23s17
+            .get(Port::GpioA, 0)
+            .expect("Failed to get pin")
+            .into_input_pin()
+            .expect("Failed to convert to InputPin"),
+    )));
+    let callback_pin = pin.clone();
+    let callback_calls = calls.clone();
+    pin.lock()
+        .unwrap()
+        .as_mut()
+        .unwrap()
+        .set_interrupt_callback(InterruptMode::ActiveLow, move |_| {
+            *callback_calls.lock().unwrap() += 1;
+            // Dropping the pin from its own callback releases it.
+            callback_pin.lock().unwrap().take();
+        })
+        .expect("Failed to set callback");
+
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0001);
+    mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(*calls.lock().unwrap(), 1);
+    assert!(pin.lock().unwrap().is_none());
+
+    // A new pin doesn't inherit the old callback.
+    let _pin = mcp23s17
+        .get(Port::GpioA, 0)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0001);
+    mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(*calls.lock().unwrap(), 1);
+}
+
+#[test]
+fn connect_interrupt_lines_polarity() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    let line = MockInterruptLine::new();
+    mcp23s17
+        .connect_interrupt_lines(vec![line.clone()])
+        .expect("Failed to connect");
+    assert_eq!(line.configured_active_high(), Some(false));
+
+    mcp23s17
+        .write(RegisterAddress::IOCON, IOCON::INTPOL_HIGH.bits())
+        .expect("Bad write");
+    let inta = MockInterruptLine::new();
+    let intb = MockInterruptLine::new();
+    mcp23s17
+        .connect_interrupt_lines(vec![inta.clone(), intb.clone()])
+        .expect("Failed to connect");
+    assert_eq!(inta.configured_active_high(), Some(true));
+    assert_eq!(intb.configured_active_high(), Some(true));
+
+    // Open-drain overrides the polarity.
+    mcp23s17
+        .write(
+            RegisterAddress::IOCON,
+            (IOCON::INTPOL_HIGH | IOCON::ODR_ON).bits(),
+        )
+        .expect("Bad write");
+    let line = MockInterruptLine::new();
+    mcp23s17
+        .connect_interrupt_lines(vec![line.clone()])
+        .expect("Failed to connect");
+    assert_eq!(line.configured_active_high(), Some(false));
+
+    assert!(matches!(
+        mcp23s17.connect_interrupt_lines(Vec::<MockInterruptLine>::new()),
+        Err(Mcp23s17Error::NoInterruptLine)
+    ));
+}
+
+#[test]
+fn wait_for_interrupts() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let mut pin = mcp23s17
+        .get(Port::GpioB, 6)
+        .expect("Failed to get pin")
+        .into_pullup_input_pin()
+        .expect("Failed to convert to InputPin");
+    let callback_levels = levels.clone();
+    pin.set_interrupt_callback(InterruptMode::ActiveLow, move |level| {
+        callback_levels.lock().unwrap().push(level)
+    })
+    .expect("Failed to set callback");
+
+    let intb = MockInterruptLine::new();
+    let mut interrupt_lines = mcp23s17
+        .connect_interrupt_lines(vec![intb.clone()])
+        .expect("Failed to connect");
+
+    // Nothing happens until the interrupt output is active.
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0100_0000);
+    assert!(!interrupt_lines.is_active().expect("Bad line"));
+    assert!(
+        interrupt_lines
+            .wait_for_interrupts(Some(Duration::from_millis(1)))
+            .expect("Bad wait")
+            .is_empty()
+    );
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTFB).1, 0);
+
+    intb.set_active(true);
+    let events = interrupt_lines.wait_for_interrupts(None).expect("Bad wait");
+    assert_eq!(
+        events,
+        vec![InterruptEvent {
+            port: Port::GpioB,
+            pin: 6,
+            level: Level::Low
+        }]
+    );
+    assert_eq!(*levels.lock().unwrap(), vec![Level::Low]);
+    assert_eq!(interrupt_lines.release().len(), 1);
+}
+
+#[test]
+fn mcp23s08_service_interrupt_lines_until() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let mut pin = mcp23s08
+        .get(7)
+        .expect("Failed to get pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    let callback_levels = levels.clone();
+    pin.set_interrupt_callback(InterruptMode::BothEdges, move |level| {
+        callback_levels.lock().unwrap().push(level)
+    })
+    .expect("Failed to set callback");
+
+    let int = MockInterruptLine::new();
+    let mut interrupt_lines = mcp23s08
+        .connect_interrupt_lines(vec![int.clone()])
+        .expect("Failed to connect");
+    assert_eq!(int.configured_active_high(), Some(false));
+
+    // Each trigger is serviced until told to stop.
+    int.set_active(true);
+    mcp23s08.set_mock_data(RegisterAddress::INTFA, 0b1000_0000);
+    mcp23s08.set_mock_data(RegisterAddress::INTCAPA, 0b1000_0000);
+    let mut serviced = Vec::new();
+    interrupt_lines
+        .service_until(|events| {
+            serviced.push(events.len());
+            if serviced.len() == 1 {
+                mcp23s08.set_mock_data(RegisterAddress::INTFA, 0b1000_0000);
+                mcp23s08.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0000);
+            }
+            serviced.len() == 3
+        })
+        .expect("Bad service");
+    assert_eq!(serviced, vec![1, 1, 0]);
+    assert_eq!(*levels.lock().unwrap(), vec![Level::High, Level::Low]);
+}
+
+#[test]
+fn subscribe_interrupt_events() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let receiver = mcp23s17.subscribe(2);
+    let before = std::time::Instant::now();
+
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0011);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0010);
+    mcp23s17.service_interrupts().expect("Bad service");
+
+    let first = receiver.try_recv().expect("Missing event");
+    assert!(first.timestamp >= before);
+    assert_eq!(
+        first.event,
+        InterruptEvent {
+            port: Port::GpioA,
+            pin: 0,
+            level: Level::Low
+        }
+    );
+    let second = receiver.try_recv().expect("Missing event");
+    assert_eq!(second.event.pin, 1);
+    assert_eq!(second.event.level, Level::High);
+    assert_eq!(mcp23s17.interrupt_overflow_count(), 0);
+
+    // Overflowing the channel is counted.
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_0111);
+    mcp23s17.take_interrupts().expect("Bad take interrupts");
+    assert_eq!(receiver.try_iter().count(), 2);
+    assert_eq!(mcp23s17.interrupt_overflow_count(), 1);
+
+    // Dropped subscribers are forgotten.
+    drop(receiver);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_0111);
+    mcp23s17.take_interrupts().expect("Bad take interrupts");
+    assert_eq!(mcp23s17.interrupt_overflow_count(), 1);
+}
+
+#[test]
+fn subscribe_with_no_capacity() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let receiver = mcp23s17.subscribe(0);
+
+    // The channel still holds one event.
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0011);
+    mcp23s17.service_interrupts().expect("Bad service");
+    assert_eq!(receiver.try_recv().expect("Missing event").event.pin, 0);
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(mcp23s17.interrupt_overflow_count(), 1);
+}
+
+#[test]
+fn config_builder() {
+    let config = Mcp23s17Config::builder().build();
+    assert_eq!(config, Mcp23s17Config::default());
+    assert_eq!(config.iocon(), IOCON::empty());
+
+    let config = Mcp23s17Config::builder()
+        .mirror(true)
+        .sequential_operation(false)
+        .slew_rate_control(false)
+        .hardware_address_enable(true)
+        .open_drain(true)
+        .interrupt_polarity(Level::High)
+        .build();
+    assert_eq!(config.iocon().bits(), 0b0111_1110);
+
+    let config = Mcp23s17Config::builder().bank(true).build();
+    assert_eq!(config.iocon(), IOCON::BANK_ON);
+}
+
+#[test]
+fn configure() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    let config = Mcp23s17Config::builder()
+        .mirror(true)
+        .hardware_address_enable(true)
+        .build();
+
+    mcp23s17.configure(&config).expect("Failed to configure");
+    // Verified by reading back from the device despite the cache (after the cache
+    // was loaded from both IOCON addresses).
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IOCON),
+        (0b0100_1000, 3, 1)
+    );
+}
+
+#[test]
+fn configure_not_applied() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi6,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let config = Mcp23s17Config::builder().mirror(true).build();
+
+    let result = mcp23s17.configure(&config);
+    assert!(
+        matches!(
+            result,
+            Err(Mcp23s17Error::ConfigurationNotApplied {
+                written: 0b0100_0000,
+                read: 0
+            })
+        ),
+        "Unexpected result: {result:?}"
+    );
+}
+
+#[test]
+fn mcp23s08_configure_mirror() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    let config = Mcp23s17Config::builder().mirror(true).build();
+    assert!(matches!(
+        mcp23s08.configure(&config),
+        Err(Mcp23s17Error::UnsupportedConfiguration(_))
+    ));
+    let config = Mcp23s17Config::builder().bank(true).build();
+    assert!(matches!(
+        mcp23s08.configure(&config),
+        Err(Mcp23s17Error::UnsupportedConfiguration(_))
+    ));
+
+    let config = Mcp23s17Config::builder()
+        .hardware_address_enable(true)
+        .build();
+    mcp23s08.configure(&config).expect("Failed to configure");
+    assert_eq!(
+        mcp23s08.get_mock_data(RegisterAddress::IOCON).0,
+        0b0000_1000
+    );
+}
+
+#[test]
+fn banked_register_layout() {
+    let layout = RegisterLayout::Banked;
+    for (register, address) in [
+        (RegisterAddress::IODIRA, 0x00),
+        (RegisterAddress::IODIRB, 0x10),
+        (RegisterAddress::IOCON, 0x05),
+        (RegisterAddress::IOCON2, 0x15),
+        (RegisterAddress::GPPUB, 0x16),
+        (RegisterAddress::OLATA, 0x0a),
+        (RegisterAddress::OLATB, 0x1a),
+    ] {
+        assert_eq!(layout.address(register).expect("No address"), address);
+        assert_eq!(layout.register(address).expect("No register"), register);
+    }
+    for index in 0..RegisterAddress::LENGTH {
+        let register = RegisterAddress::try_from(index).unwrap();
+        let address = layout.address(register).expect("No address");
+        assert_eq!(layout.register(address).expect("No register"), register);
+    }
+    assert!(layout.register(0x0b).is_err());
+    assert!(layout.register(0x1b).is_err());
+}
+
+#[test]
+fn set_bank_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Interleaved);
+    mcp23s17
+        .write(RegisterAddress::IOCON, 0b0000_1000)
+        .expect("Bad write");
+
+    mcp23s17
+        .set_bank_mode(BankMode::Segregated)
+        .expect("Failed to set bank mode");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Segregated);
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IOCON).0,
+        0b1000_1000
+    );
+
+    // The registers are now found at their banked addresses.
+    mcp23s17
+        .write(RegisterAddress::GPPUB, 0x5a)
+        .expect("Bad write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPPUB), (0x5a, 0, 1));
+    mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0x12);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPIOA).expect("Bad read"),
+        0x12
+    );
+
+    mcp23s17
+        .set_bank_mode(BankMode::Interleaved)
+        .expect("Failed to set bank mode");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Interleaved);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IOCON).expect("Bad read"),
+        0b0000_1000
+    );
+}
+
+#[test]
+fn configure_bank_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    let config = Mcp23s17Config::builder().bank(true).mirror(true).build();
+
+    mcp23s17.configure(&config).expect("Failed to configure");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Segregated);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IOCON2).expect("Bad read"),
+        0b1100_0000
+    );
+}
+
+#[test]
+fn assume_bank_mode() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    // Left in BANK=1 by other software.
+    mcp23s17.set_mock_data(RegisterAddress::IOCON, 0b1000_0000);
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0xa5);
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+
+    mcp23s17
+        .assume_bank_mode(BankMode::Segregated)
+        .expect("Failed to assume bank mode");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Segregated);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IOCON).2, 0);
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPIOB).expect("Bad read"),
+        0xa5
+    );
+    // The cache was reloaded from the banked addresses.
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IODIRB).expect("Bad read"),
+        0xff
+    );
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::IOCON).expect("Bad read"),
+        0b1000_0000
+    );
+}
+
+#[test]
+fn bank_mode_single_port() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    // The BANK bit is not implemented so does not change the layout.
+    mcp23s08
+        .write(RegisterAddress::IOCON, 0b1000_0000)
+        .expect("Bad write");
+    mcp23s08.set_mock_data(RegisterAddress::GPIOA, 0x3c);
+    assert_eq!(
+        mcp23s08.read(RegisterAddress::GPIOA).expect("Bad read"),
+        0x3c
+    );
+}
+
+#[test]
+fn banked_16_bit_access() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17
+        .set_bank_mode(BankMode::Segregated)
+        .expect("Failed to set bank mode");
+
+    mcp23s17.set_mock_data(RegisterAddress::GPIOA, 0x34);
+    mcp23s17.set_mock_data(RegisterAddress::GPIOB, 0x12);
+    assert_eq!(mcp23s17.read_gpio16().expect("Bad read"), 0x1234);
+
+    mcp23s17.write_olat16(0xbeef).expect("Bad write");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATA).0, 0xef);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB).0, 0xbe);
+    mcp23s17.modify_olat16(0x0ff0, 0x1234).expect("Bad modify");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATA).0, 0x3f);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::OLATB).0, 0xb2);
+
+    mcp23s17.set_mock_data(RegisterAddress::INTFA, 0b0000_0001);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPA, 0b0000_0001);
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b1000_0000);
+    let events = mcp23s17.take_interrupts().expect("Bad take");
+    assert_eq!(
+        events,
+        vec![
+            InterruptEvent {
+                port: Port::GpioA,
+                pin: 0,
+                level: Level::High
+            },
+            InterruptEvent {
+                port: Port::GpioB,
+                pin: 7,
+                level: Level::Low
+            },
+        ]
+    );
+}
+
+#[test]
+fn banked_burst_bounds() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17
+        .set_bank_mode(BankMode::Segregated)
+        .expect("Failed to set bank mode");
+
+    // GPIOA, OLATA is the end of bank A.
+    let mut data = [0u8; 3];
+    assert!(matches!(
+        mcp23s17.read_registers(RegisterAddress::GPIOA, &mut data),
+        Err(Mcp23s17Error::RegisterAddressBoundsError)
+    ));
+    mcp23s17
+        .read_registers(RegisterAddress::GPIOA, &mut data[..2])
+        .expect("Bad read");
+
+    // Bursts must not switch the bank mode.
+    assert!(matches!(
+        mcp23s17.write_registers(RegisterAddress::INTCONA, &[0x00, 0x00]),
+        Err(Mcp23s17Error::UnsupportedConfiguration(_))
+    ));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTCONA).2, 0);
+    mcp23s17
+        .write_registers(RegisterAddress::INTCONA, &[0x00, 0x80])
+        .expect("Bad write");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Segregated);
+}
+
+#[test]
+fn bus_enables_hardware_addressing() {
+    let addresses = [
+        HardwareAddress::new(0).unwrap(),
+        HardwareAddress::new(3).unwrap(),
+    ];
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    )
+    .expect("Create bus");
+    assert_eq!(bus.get_spi_bus(), Some(SpiBus::Spi0));
+
+    // Both devices answered the write to address 0 since HAEN was disabled, after which
+    // each only answered to its own address.
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::IOCON),
+        (0b0000_1000, 1, 1)
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::IOCON),
+        (0b0000_1000, 1, 2)
+    );
+}
+
+#[test]
+fn bus_hardware_addressing_writes_only_haen() {
+    let addresses = [
+        HardwareAddress::new(1).unwrap(),
+        HardwareAddress::new(2).unwrap(),
+    ];
+    let spi = mock_spi::MockSpiBus::new(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    );
+    spi.device(addresses[0])
+        .set_mock_data(RegisterAddress::IOCON, (IOCON::MIRROR | IOCON::ODR).bits());
+    spi.device(addresses[1])
+        .set_mock_data(RegisterAddress::IOCON, (IOCON::BANK | IOCON::MIRROR).bits());
+    let bus = Mcp23s17Bus::with_transport(spi).expect("Create bus");
+    assert_eq!(bus.get_spi_bus(), None);
+
+    // Neither device's other IOCON bits are written to either device.
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::IOCON).0,
+        IOCON::HAEN.bits()
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::GPINTENB).2,
+        0
+    );
+
+    // The device in the banked layout has IOCON at 0x05 and OLATA at 0x0A, which
+    // isn't touched.
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::IOCON).0,
+        (IOCON::BANK | IOCON::HAEN).bits()
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::OLATA),
+        (0x00, 0, 0)
+    );
+
+    let second = bus.get(addresses[1]).expect("Failed to get MCP23S17");
+    second
+        .assume_bank_mode(BankMode::Segregated)
+        .expect("Failed to assume bank mode");
+    second
+        .write(RegisterAddress::OLATB, 0x81)
+        .expect("Bad write");
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::OLATB),
+        (0x81, 0, 1)
+    );
+    assert_eq!(bus.get_mock_data(addresses[0], RegisterAddress::OLATB).2, 0);
+}
+
+#[test]
+fn bus_devices_keep_hardware_addressing() {
+    let addresses = [
+        HardwareAddress::new(0).unwrap(),
+        HardwareAddress::new(3).unwrap(),
+    ];
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    )
+    .expect("Create bus");
+    let first = bus.get(addresses[0]).expect("Failed to get MCP23S17");
+    let second = bus.get(addresses[1]).expect("Failed to get MCP23S17");
+
+    // A default configuration doesn't enable HAEN, but the bus devices keep it set.
+    first
+        .configure(&Mcp23s17Config::builder().mirror(true).build())
+        .expect("Failed to configure");
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::IOCON).0,
+        (IOCON::MIRROR | IOCON::HAEN).bits()
+    );
+    second
+        .write(RegisterAddress::IOCON, 0x00)
+        .expect("Bad write");
+    second
+        .write_registers(RegisterAddress::INTCONA, &[0x00, 0x00, 0x00, 0x00])
+        .expect("Bad write");
+    second
+        .write_registers(RegisterAddress::IOCON2, &[IOCON::SEQOP.bits()])
+        .expect("Bad write");
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::IOCON).0,
+        (IOCON::SEQOP | IOCON::HAEN).bits()
+    );
+
+    second
+        .write(RegisterAddress::GPPUA, 0xf0)
+        .expect("Bad write");
+    assert_eq!(bus.get_mock_data(addresses[0], RegisterAddress::GPPUA).2, 0);
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::GPPUA),
+        (0xf0, 0, 1)
+    );
+}
+
+#[test]
+fn bus_devices_addressed() {
+    let addresses = [
+        HardwareAddress::new(0).unwrap(),
+        HardwareAddress::new(3).unwrap(),
+    ];
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    )
+    .expect("Create bus");
+    let first = bus.get(addresses[0]).expect("Failed to get MCP23S17");
+    let second = bus.get(addresses[1]).expect("Failed to get MCP23S17");
+    assert_eq!(first.get_hardware_address(), addresses[0]);
+
+    first
+        .write(RegisterAddress::GPPUA, 0x0f)
+        .expect("Bad write");
+    second
+        .write(RegisterAddress::GPPUA, 0xf0)
+        .expect("Bad write");
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::GPPUA),
+        (0x0f, 0, 1)
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::GPPUA),
+        (0xf0, 0, 1)
+    );
+
+    bus.set_mock_data(addresses[1], RegisterAddress::GPIOB, 0x81);
+    assert_eq!(second.read(RegisterAddress::GPIOB).expect("Bad read"), 0x81);
+    assert_eq!(first.read(RegisterAddress::GPIOB).expect("Bad read"), 0);
+}
+
+#[test]
+fn bus_address_in_use() {
+    let address = HardwareAddress::new(5).unwrap();
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &[address],
+    )
+    .expect("Create bus");
+
+    let mcp23s17 = bus.get(address).expect("Failed to get MCP23S17");
+    assert!(matches!(
+        bus.get(address),
+        Err(Mcp23s17Error::HardwareAddressInUse(5))
+    ));
+
+    // The pins keep the address in use.
+    let pin = mcp23s17.get(Port::GpioA, 0).expect("Failed to get Pin");
+    drop(mcp23s17);
+    assert!(matches!(
+        bus.get(address),
+        Err(Mcp23s17Error::HardwareAddressInUse(5))
+    ));
+    drop(pin);
+    bus.get(address).expect("Failed to get MCP23S17");
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_send_between_threads() {
+    fn assert_send_sync<S: Send + Sync>() {}
+    assert_send_sync::<Mcp23s17<MockSpi>>();
+    assert_send_sync::<OutputPin<MockSpi>>();
+    assert_send_sync::<InputPin<MockSpi>>();
+    assert_send_sync::<PortBus<MockSpi>>();
+
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let pin = mcp23s17
+        .get(Port::GpioA, 3)
+        .expect("Failed to get Pin")
+        .into_output_pin_high()
+        .expect("Failed to convert to OutputPin");
+
+    std::thread::spawn(move || pin.set_low().expect("Bad pin write"))
+        .join()
+        .expect("Thread panicked");
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPIOA).0, 0);
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_read_modify_write_atomic() {
+    let mcp23s17 = Arc::new(
+        Mcp23s17::new_mock(
+            HardwareAddress::new(0).unwrap(),
+            SpiBus::Spi0,
+            ChipSelect::Cs0,
+            100_000,
+            SpiMode::Mode0,
+        )
+        .expect("Create MCP23S17"),
+    );
+
+    // Each thread repeatedly flips its own bit; no update may be lost.
+    let threads: Vec<_> = (0..8)
+        .map(|bit| {
+            let mcp23s17 = mcp23s17.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    mcp23s17
+                        .set_bit(RegisterAddress::GPPUA, bit)
+                        .expect("Bad set");
+                    mcp23s17
+                        .clear_bit(RegisterAddress::GPPUA, bit)
+                        .expect("Bad clear");
+                }
+                mcp23s17
+                    .set_bit(RegisterAddress::GPPUA, bit)
+                    .expect("Bad set");
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("Thread panicked");
+    }
+    assert_eq!(
+        mcp23s17.read(RegisterAddress::GPPUA).expect("Bad read"),
+        0xff
+    );
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn sync_interrupt_callback() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    let levels = Arc::new(Mutex::new(Vec::new()));
+    let mut pin = mcp23s17
+        .get(Port::GpioB, 1)
+        .expect("Failed to get Pin")
+        .into_input_pin()
+        .expect("Failed to convert to InputPin");
+    let callback_levels = levels.clone();
+    pin.set_interrupt_callback(InterruptMode::BothEdges, move |level| {
+        callback_levels.lock().unwrap().push(level);
+    })
+    .expect("Failed to set callback");
+
+    mcp23s17.set_mock_data(RegisterAddress::INTFB, 0b0000_0010);
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0b0000_0010);
+    std::thread::spawn(move || mcp23s17.service_interrupts().expect("Bad service"))
+        .join()
+        .expect("Thread panicked");
+    assert_eq!(*levels.lock().unwrap(), vec![Level::High]);
+}
+
+#[cfg(feature = "process-lock")]
+#[test]
+fn process_lock_default_path() {
+    assert_eq!(
+        ProcessLock::default_path(
+            SpiBus::Spi0,
+            ChipSelect::Cs1,
+            HardwareAddress::new(3).unwrap()
+        ),
+        std::path::Path::new("/run/lock/rppal-mcp23s17-Spi0-Cs1-3.lock")
+    );
+}
+
+#[cfg(feature = "process-lock")]
+#[test]
+fn process_lock_around_read_modify_write() {
+    use std::{fs::File, os::fd::AsRawFd, thread, time::Instant};
+
+    let path = std::env::temp_dir().join(format!(
+        "rppal-mcp23s17-test-{}-process-lock.lock",
+        std::process::id()
+    ));
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    mcp23s17
+        .enable_process_lock(&path)
+        .expect("Failed to enable process lock");
+
+    // Another "process" holds the lock and changes the register behind the cache.
+    let other = File::open(&path).expect("Failed to open lock file");
+    assert_eq!(unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_EX) }, 0);
+    mcp23s17.set_mock_data(RegisterAddress::GPPUA, 0b1000_0000);
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        drop(other);
+    });
+
+    let start = Instant::now();
+    mcp23s17
+        .set_bit(RegisterAddress::GPPUA, 0)
+        .expect("Bad set");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    release.join().expect("Thread panicked");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::GPPUA).0,
+        0b1000_0001
+    );
+
+    mcp23s17.disable_process_lock();
+    std::fs::remove_file(&path).expect("Failed to remove lock file");
+}
+
+#[test]
+fn probe() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::DEFVALA, 0x3c);
+
+    mcp23s17.probe().expect("Device not found");
+    // Both patterns were written and then the original value restored.
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::DEFVALA),
+        (0x3c, 3, 3)
+    );
+}
+
+#[test]
+fn probe_not_responding() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi6,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+
+    assert!(matches!(
+        mcp23s17.probe(),
+        Err(Mcp23s17Error::DeviceNotResponding)
+    ));
+
+    let config = Mcp23s17Config::builder().probe(true).mirror(true).build();
+    assert!(config.probe());
+    assert!(matches!(
+        mcp23s17.configure(&config),
+        Err(Mcp23s17Error::DeviceNotResponding)
+    ));
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IOCON).2, 0);
+}
+
+#[test]
+fn probe_i2c_and_single_port() {
+    let mcp23017 = Mcp23017::new_mock_i2c(HardwareAddress::new(4).unwrap());
+    mcp23017.probe().expect("Device not found");
+
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08.probe().expect("Device not found");
+    let config = Mcp23s17Config::builder().probe(true).build();
+    mcp23s08.configure(&config).expect("Failed to configure");
+}
+
+#[test]
+fn bus_discover() {
+    let addresses = [
+        HardwareAddress::new(1).unwrap(),
+        HardwareAddress::new(4).unwrap(),
+        HardwareAddress::new(7).unwrap(),
+    ];
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    )
+    .expect("Create bus");
+    bus.set_mock_data(addresses[1], RegisterAddress::DEFVALA, 0x81);
+
+    assert_eq!(bus.discover().expect("Bad discover"), addresses);
+    // The probe left the registers as they were.
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::DEFVALA).0,
+        0x81
+    );
+
+    // An address in use is reported without being probed.
+    let _mcp23s17 = bus.get(addresses[2]).expect("Failed to get MCP23S17");
+    let reads = bus.get_mock_data(addresses[2], RegisterAddress::DEFVALA).1;
+    assert_eq!(bus.discover().expect("Bad discover"), addresses);
+    assert_eq!(
+        bus.get_mock_data(addresses[2], RegisterAddress::DEFVALA).1,
+        reads
+    );
+}
+
+#[test]
+fn bus_discover_none() {
+    let bus = Mcp23s17Bus::new_mock(SpiBus::Spi0, ChipSelect::Cs0, 100_000, SpiMode::Mode0, &[])
+        .expect("Create bus");
+    assert_eq!(bus.discover().expect("Bad discover"), vec![]);
+}
+
+#[test]
+fn reset_to_por_defaults() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.enable_cache().expect("Failed to enable cache");
+    mcp23s17
+        .set_bank_mode(BankMode::Segregated)
+        .expect("Failed to set bank mode");
+    for register in [
+        RegisterAddress::IODIRA,
+        RegisterAddress::IPOLB,
+        RegisterAddress::GPINTENA,
+        RegisterAddress::DEFVALB,
+        RegisterAddress::INTCONA,
+        RegisterAddress::GPPUB,
+        RegisterAddress::OLATA,
+    ] {
+        mcp23s17.write(register, 0x55).expect("Bad write");
+    }
+    mcp23s17.set_mock_data(RegisterAddress::INTCAPB, 0x0f);
+
+    mcp23s17.reset_to_por_defaults().expect("Failed to reset");
+    assert_eq!(mcp23s17.bank_mode(), BankMode::Interleaved);
+    for register in [RegisterAddress::IODIRA, RegisterAddress::IODIRB] {
+        assert_eq!(mcp23s17.get_mock_data(register).0, 0xff);
+        assert_eq!(mcp23s17.read(register).expect("Bad read"), 0xff);
+    }
+    for register in [
+        RegisterAddress::IOCON,
+        RegisterAddress::IPOLB,
+        RegisterAddress::GPINTENA,
+        RegisterAddress::DEFVALB,
+        RegisterAddress::INTCONA,
+        RegisterAddress::GPPUB,
+        RegisterAddress::OLATA,
+    ] {
+        assert_eq!(mcp23s17.get_mock_data(register).0, 0x00);
+        assert_eq!(mcp23s17.read(register).expect("Bad read"), 0x00);
+    }
+    // The pending interrupt was cleared by reading INTCAP.
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::INTCAPB).1, 1);
+}
+
+#[test]
+fn reset_keeps_hardware_addressing() {
+    let addresses = [
+        HardwareAddress::new(0).unwrap(),
+        HardwareAddress::new(3).unwrap(),
+    ];
+    let bus = Mcp23s17Bus::new_mock(
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+        &addresses,
+    )
+    .expect("Create bus");
+    let first = bus.get(addresses[0]).expect("Failed to get MCP23S17");
+    let second = bus.get(addresses[1]).expect("Failed to get MCP23S17");
+    first
+        .write(RegisterAddress::GPPUA, 0x0f)
+        .expect("Bad write");
+
+    first.reset_to_por_defaults().expect("Failed to reset");
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::IOCON).0,
+        IOCON::HAEN.bits()
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::GPPUA).0,
+        0x00
+    );
+
+    // The reset device still only answers to its own address.
+    second
+        .write(RegisterAddress::GPPUA, 0xf0)
+        .expect("Bad write");
+    assert_eq!(
+        bus.get_mock_data(addresses[0], RegisterAddress::GPPUA),
+        (0x00, 0, 2)
+    );
+    assert_eq!(
+        bus.get_mock_data(addresses[1], RegisterAddress::GPPUA),
+        (0xf0, 0, 1)
+    );
+
+    // So does a device that was configured with HAEN on its own chip select.
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(2).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17
+        .write(RegisterAddress::IOCON, 0b0110_1000)
+        .expect("Bad write");
+    mcp23s17.reset_to_por_defaults().expect("Failed to reset");
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::IOCON).0,
+        IOCON::HAEN.bits()
+    );
+}
+
+#[test]
+fn configure_with_reset() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08.set_mock_data(RegisterAddress::IODIRA, 0x00);
+    mcp23s08.set_mock_data(RegisterAddress::OLATA, 0x3c);
+    let config = Mcp23s17Config::builder()
+        .reset(true)
+        .hardware_address_enable(true)
+        .build();
+    assert!(config.reset());
+
+    mcp23s08.configure(&config).expect("Failed to configure");
+    assert_eq!(mcp23s08.get_mock_data(RegisterAddress::IODIRA).0, 0xff);
+    assert_eq!(mcp23s08.get_mock_data(RegisterAddress::OLATA).0, 0x00);
+    assert_eq!(
+        mcp23s08.get_mock_data(RegisterAddress::IOCON).0,
+        0b0000_1000
+    );
+}
+
+#[test]
+fn attach_pins() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::IODIRA, 0b1111_0000);
+    mcp23s17.set_mock_data(RegisterAddress::OLATA, 0b0000_0101);
+    mcp23s17.set_mock_data(RegisterAddress::GPPUA, 0b0001_0000);
+    mcp23s17.set_mock_data(RegisterAddress::IPOLA, 0b0010_0000);
+    mcp23s17.set_mock_data(RegisterAddress::GPINTENA, 0b1110_0000);
+    mcp23s17.set_mock_data(RegisterAddress::INTCONA, 0b1100_0000);
+    mcp23s17.set_mock_data(RegisterAddress::DEFVALA, 0b1000_0000);
+    mcp23s17.set_mock_data(RegisterAddress::IODIRB, 0xff);
+
+    let pins = mcp23s17.attach_pins().expect("Failed to attach");
+    assert_eq!(pins.len(), 16);
+    for (index, pin) in pins.iter().enumerate().take(4) {
+        match pin {
+            AttachedPin::Output(pin, level) => {
+                assert_eq!(pin.get_pin_number(), index as u8);
+                assert_eq!(*level, Level::from(index % 2 == 0));
+            }
+            AttachedPin::Input(_) => panic!("Pin {index} should be an output"),
+        }
+    }
+    let expected = [
+        (InputPinMode::PullUp, false, InterruptMode::None),
+        (InputPinMode::HighImpedance, true, InterruptMode::BothEdges),
+        (
+            InputPinMode::HighImpedance,
+            false,
+            InterruptMode::ActiveHigh,
+        ),
+        (InputPinMode::HighImpedance, false, InterruptMode::ActiveLow),
+    ];
+    for (pin, (mode, inverted, interrupt_mode)) in pins[4..8].iter().zip(expected) {
+        let AttachedPin::Input(pin) = pin else {
+            panic!("Expected an input: {pin:?}");
+        };
+        assert_eq!(pin.mode(), mode);
+        assert_eq!(pin.is_inverted(), inverted);
+        assert_eq!(pin.interrupt_mode(), interrupt_mode);
+    }
+    assert!(
+        pins[8..]
+            .iter()
+            .all(|pin| matches!(pin, AttachedPin::Input(_)))
+    );
+
+    // Attaching wrote nothing.
+    for register in [
+        RegisterAddress::IODIRA,
+        RegisterAddress::IODIRB,
+        RegisterAddress::IPOLA,
+        RegisterAddress::GPINTENA,
+        RegisterAddress::DEFVALA,
+        RegisterAddress::INTCONA,
+        RegisterAddress::IOCON,
+        RegisterAddress::GPPUA,
+        RegisterAddress::GPIOA,
+        RegisterAddress::OLATA,
+    ] {
+        assert_eq!(
+            mcp23s17.get_mock_data(register).2,
+            0,
+            "{register:?} written"
+        );
+    }
+    assert!(matches!(
+        mcp23s17.get(Port::GpioB, 0),
+        Err(Mcp23s17Error::PinNotAvailable(0))
+    ));
+
+    // Like any other InputPin, dropping the attached pins disables their interrupts
+    // and inversion.
+    drop(pins);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::GPINTENA).0, 0);
+    assert_eq!(mcp23s17.get_mock_data(RegisterAddress::IPOLA).0, 0);
+    assert_eq!(
+        mcp23s17.get_mock_data(RegisterAddress::OLATA).0,
+        0b0000_0101
+    );
+}
+
+#[test]
+fn attach_pins_in_use() {
+    let mcp23s17 = Mcp23s17::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S17");
+    mcp23s17.set_mock_data(RegisterAddress::IODIRA, 0xff);
+    mcp23s17.set_mock_data(RegisterAddress::GPINTENA, 0xff);
+    mcp23s17.set_mock_data(RegisterAddress::IODIRB, 0x00);
+    let pin = mcp23s17.get(Port::GpioB, 3).expect("Failed to get Pin");
+
+    assert!(matches!(
+        mcp23s17.attach_pins(),
+        Err(Mcp23s17Error::PinNotAvailable(3))
+    ));
+    // No port A pin was attached so none disabled its interrupts on being dropped.
+    let (gpintena, _, writes) = mcp23s17.get_mock_data(RegisterAddress::GPINTENA);
+    assert_eq!((gpintena, writes), (0xff, 0));
+
+    let AttachedPin::Output(pin, level) = pin.into_attached_pin().expect("Failed to attach") else {
+        panic!("Expected an output");
+    };
+    assert_eq!(level, Level::Low);
+    assert_eq!(pin.get_pin_number(), 3);
+}
+
+#[test]
+fn mcp23s08_attach_pins() {
+    let mcp23s08 = Mcp23s08::new_mock(
+        HardwareAddress::new(0).unwrap(),
+        SpiBus::Spi0,
+        ChipSelect::Cs0,
+        100_000,
+        SpiMode::Mode0,
+    )
+    .expect("Create MCP23S08");
+    mcp23s08.set_mock_data(RegisterAddress::IODIRA, 0b0000_1111);
+
+    let pins = mcp23s08.attach_pins().expect("Failed to attach");
+    assert_eq!(pins.len(), 8);
+    assert!(matches!(pins[3], AttachedPin::Input(_)));
+    assert!(matches!(pins[4], AttachedPin::Output(_, Level::Low)));
+    assert_eq!(mcp23s08.get_mock_data(RegisterAddress::IODIRA).2, 0);
+}
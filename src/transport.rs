@@ -0,0 +1,154 @@
+//! The transport that carries messages between the driver and the MCP23S17.
+//!
+//! The driver frames every register access as an SPI message (control byte, register
+//! address and then the data) and hands it to a [`Transport`] to be clocked out to the
+//! device. An implementation is provided for the RPPAL [`Spi`] and, in testing
+//! environments, for the `MockSpi`. Any other type that can perform a full-duplex
+//! transfer can be used by implementing [`Transport`] and creating the device with
+//! [`Mcp23s17::with_transport()`][crate::Mcp23s17::with_transport].
+//!
+//! With the `embedded-hal` feature enabled, any `embedded_hal::spi::SpiDevice` can be
+//! used through the `SpiDeviceTransport` adapter.
+//!
+//! The MCP23017 is the I2C twin of the MCP23S17 and its I2C address byte is identical
+//! to the SPI control byte, so the [`I2cTransport`] carries the same messages over any
+//! [`I2cBus`] (_e.g._ the RPPAL [`I2c`]).
+
+use rppal::{i2c::I2c, spi::Spi};
+
+use super::{Mcp23s17Error, Result};
+
+/// A full-duplex byte transport to an MCP23S17.
+///
+/// Implementations that have errors of their own to report should wrap them in
+/// [`TransportError`][crate::Mcp23s17Error::TransportError].
+pub trait Transport {
+    /// Write the bytes in `write_buffer` to the device whilst simultaneously reading the
+    /// same number of bytes into `read_buffer`.
+    ///
+    /// The first byte of `write_buffer` is the control byte, the second is the register
+    /// address and any remaining bytes are the register data. Returns the number of
+    /// bytes transferred.
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize>;
+}
+
+impl Transport for Spi {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        Ok(Spi::transfer(self, read_buffer, write_buffer)?)
+    }
+}
+
+/// The I2C operations needed to talk to an MCP23017.
+pub trait I2cBus {
+    /// Set the 7-bit address of the device that subsequent operations address.
+    fn set_slave_address(&mut self, slave_address: u16) -> Result<()>;
+
+    /// Write the contents of `buffer` to the device in a single I2C write.
+    fn write(&mut self, buffer: &[u8]) -> Result<()>;
+
+    /// Write the contents of `write_buffer` to the device and then, after a repeated
+    /// start, fill `read_buffer` from the device.
+    fn write_read(&mut self, write_buffer: &[u8], read_buffer: &mut [u8]) -> Result<()>;
+}
+
+impl I2cBus for I2c {
+    fn set_slave_address(&mut self, slave_address: u16) -> Result<()> {
+        Ok(I2c::set_slave_address(self, slave_address)?)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        let length = I2c::write(self, buffer)?;
+        if length != buffer.len() {
+            return Err(Mcp23s17Error::UnexpectedReadLength(length));
+        }
+        Ok(())
+    }
+
+    fn write_read(&mut self, write_buffer: &[u8], read_buffer: &mut [u8]) -> Result<()> {
+        Ok(I2c::write_read(self, write_buffer, read_buffer)?)
+    }
+}
+
+/// Carries the driver's messages to an MCP23017 over an [`I2cBus`].
+///
+/// The MCP23017's I2C address byte is the same as the control byte of the MCP23S17 SPI
+/// message, so the device address is taken from the control byte and the remainder of
+/// the message becomes an I2C write, or a write of the register address followed by a
+/// read.
+#[derive(Debug)]
+pub struct I2cTransport<I2C = I2c> {
+    pub(crate) i2c: I2C,
+    /// The slave address last set on the bus so that it is only changed when needed.
+    slave_address: Option<u16>,
+}
+
+impl<I2C: I2cBus> I2cTransport<I2C> {
+    /// Wrap the I2C bus so that it can be used as a [`Transport`].
+    pub fn new(i2c: I2C) -> Self {
+        I2cTransport {
+            i2c,
+            slave_address: None,
+        }
+    }
+
+    /// Consume the adapter, returning the wrapped I2C bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C: I2cBus> Transport for I2cTransport<I2C> {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        let slave_address = u16::from(write_buffer[0] >> 1);
+        if self.slave_address != Some(slave_address) {
+            self.i2c.set_slave_address(slave_address)?;
+            self.slave_address = Some(slave_address);
+        }
+
+        if write_buffer[0] & 0x01 != 0 {
+            // Read: there is no full-duplex on I2C so the bytes clocked out alongside the
+            // control byte and register address are simply reported as zero.
+            read_buffer[..2].fill(0);
+            self.i2c
+                .write_read(&write_buffer[1..2], &mut read_buffer[2..])?;
+        } else {
+            self.i2c.write(&write_buffer[1..])?;
+        }
+        Ok(write_buffer.len())
+    }
+}
+
+/// Adapter that allows any [`embedded_hal::spi::SpiDevice`] to act as a [`Transport`].
+///
+/// The `SpiDevice` is responsible for managing the chip select line so each register
+/// access is performed as a single transaction.
+#[cfg(feature = "embedded-hal")]
+#[derive(Debug)]
+pub struct SpiDeviceTransport<SPI> {
+    spi: SPI,
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<SPI: embedded_hal::spi::SpiDevice> SpiDeviceTransport<SPI> {
+    /// Wrap the `SpiDevice` so that it can be used as a [`Transport`].
+    pub fn new(spi: SPI) -> Self {
+        SpiDeviceTransport { spi }
+    }
+
+    /// Consume the adapter, returning the wrapped `SpiDevice`.
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl<SPI: embedded_hal::spi::SpiDevice> Transport for SpiDeviceTransport<SPI> {
+    fn transfer(&mut self, read_buffer: &mut [u8], write_buffer: &[u8]) -> Result<usize> {
+        use embedded_hal::spi::Error;
+
+        self.spi
+            .transfer(read_buffer, write_buffer)
+            .map_err(|e| Mcp23s17Error::SpiDeviceError(e.kind()))?;
+        Ok(write_buffer.len().max(read_buffer.len()))
+    }
+}